@@ -0,0 +1,195 @@
+//! This module provides `rlox build`, which translates a script into a C
+//! program that behaves like the vm running it. The compiler's stack code
+//! is translated an instruction at a time into calls to a small runtime,
+//! `runtime.c`, which is copied into every program so it builds with
+//! nothing but `cc`.
+use std::{fmt::Write, fs, path::Path};
+
+use crate::{
+    byte_code::{Location, OpCode},
+    compiler::{CompilerError, Parser},
+    error::Error,
+    globals::Globals,
+    value::Value,
+    Options,
+};
+
+const RUNTIME: &str = include_str!("runtime.c");
+
+/// The options of the vm that a C program has no use for.
+const VM_ONLY: [&str; 5] = [
+    "--gc=",
+    "--gc-stats",
+    "--max-heap=",
+    "--backend=",
+    "--disassemble",
+];
+
+/// Settings taken from the command line after `build`.
+pub(crate) struct BuildOptions {
+    script: String,
+    /// Where the C program is written.
+    output: String,
+    /// The options the script is compiled with.
+    options: Options,
+}
+
+impl BuildOptions {
+    pub(crate) fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let (mut output, mut rest) = (None, Vec::new());
+        while let Some(arg) = args.next() {
+            if arg == "-o" {
+                output = Some(args.next().ok_or("Expected a file after '-o'.")?);
+            } else if let Some(option) = VM_ONLY.iter().find(|option| arg.starts_with(*option)) {
+                let option = option.trim_end_matches('=');
+                return Err(format!("Option '{option}' doesn't apply to a build."));
+            } else {
+                rest.push(arg);
+            }
+        }
+        let options = Options::parse(rest.into_iter())?;
+        let script = options.script.clone().ok_or("No script to build.")?;
+        let output = output.unwrap_or_else(|| {
+            let output = Path::new(&script).with_extension("c");
+            output.to_string_lossy().into_owned()
+        });
+        Ok(Self {
+            script,
+            output,
+            options,
+        })
+    }
+}
+
+/// Compiles the script and writes it out as a C program.
+pub(crate) fn run(build: &BuildOptions) -> Result<(), Error> {
+    let source = fs::read_to_string(&build.script)?;
+    let mut heap = build.options.heap();
+    let mut globals = Globals::new();
+    let code = Parser::new(&source, heap.allocator(), &mut globals)
+        .with_optimizations(!build.options.no_optimize)
+        .collect::<Result<Vec<_>, CompilerError>>()?;
+    let c = translate(&code, &globals, build.options.max_stack);
+    fs::write(&build.output, c)?;
+    Ok(())
+}
+
+/// Translates the stack code of a whole script into a C program. The C
+/// stack mirrors the vm's, so locals are found in the same slots and it
+/// overflows where the vm's would, and every jump target gets a label.
+fn translate(code: &[(OpCode, Location)], globals: &Globals, max_stack: Option<usize>) -> String {
+    let target = |i: usize, offset: u16| i + 1 + offset as usize;
+    let mut targets = vec![false; code.len() + 1];
+    for (i, (op_code, _)) in code.iter().enumerate() {
+        if let OpCode::Jump(offset) | OpCode::JumpIfFalse(offset) = *op_code {
+            targets[target(i, offset)] = true;
+        }
+    }
+    let mut c = String::new();
+    if let Some(max_stack) = max_stack {
+        writeln!(c, "#define STACK_MAX {max_stack}").unwrap();
+    }
+    writeln!(c, "{RUNTIME}").unwrap();
+    let globals: String = globals
+        .iter()
+        .map(|global| {
+            let name = c_string(&global.name.to_string());
+            format!("    {{{name}, false, false, {{VAL_NIL, {{0}}}}}},\n")
+        })
+        .collect();
+    if !globals.is_empty() {
+        writeln!(c, "static Global globals[] = {{\n{globals}}};\n").unwrap();
+    }
+    c.push_str("int main(void) {\n");
+    for (i, &(op_code, location)) in code.iter().enumerate() {
+        if targets[i] {
+            writeln!(c, "l{i}:;").unwrap();
+        }
+        let at = format!("{}, {}", location.line, location.column);
+        let statement = match op_code {
+            OpCode::Constant(v) => format!("rt_push({}, {at});", c_value(v, &at)),
+            OpCode::Nil => format!("rt_push(NIL_VAL, {at});"),
+            OpCode::True => format!("rt_push(BOOL_VAL(true), {at});"),
+            OpCode::False => format!("rt_push(BOOL_VAL(false), {at});"),
+            OpCode::Add => format!("rt_add({at});"),
+            OpCode::Sub => format!("rt_sub({at});"),
+            OpCode::Mul => format!("rt_mul({at});"),
+            OpCode::Div => format!("rt_div({at});"),
+            OpCode::Greater => format!("rt_greater({at});"),
+            OpCode::Less => format!("rt_less({at});"),
+            OpCode::GreaterEqual => format!("rt_greater_equal({at});"),
+            OpCode::LessEqual => format!("rt_less_equal({at});"),
+            OpCode::Equal => format!("rt_equal(true, {at});"),
+            OpCode::NotEqual => format!("rt_equal(false, {at});"),
+            OpCode::AddConstant(v) => format!("PUSH({}); rt_add({at});", c_value(v, &at)),
+            OpCode::SubConstant(v) => format!("PUSH({}); rt_sub({at});", c_value(v, &at)),
+            OpCode::AddLocal(slot) => format!("PUSH(stack[{slot}]); rt_add({at});"),
+            OpCode::SubLocal(slot) => format!("PUSH(stack[{slot}]); rt_sub({at});"),
+            OpCode::Neg => format!("rt_negate({at});"),
+            OpCode::Not => "rt_not();".to_string(),
+            OpCode::GetLocal(slot) => format!("rt_push(stack[{slot}], {at});"),
+            OpCode::SetLocal(slot) => format!("stack[{slot}] = PEEK();"),
+            OpCode::SetLocalPop(slot) => format!("stack[{slot}] = POP();"),
+            OpCode::GetGlobal(slot) => format!("rt_get_global(&globals[{slot}], {at});"),
+            OpCode::SetGlobal(slot) => format!("rt_set_global(&globals[{slot}], {at});"),
+            OpCode::SetGlobalPop(slot) => {
+                format!("rt_set_global(&globals[{slot}], {at}); sp--;")
+            }
+            OpCode::DefineGlobal(slot) => {
+                format!("rt_define_global(&globals[{slot}], false, {at});")
+            }
+            OpCode::DefineConstGlobal(slot) => {
+                format!("rt_define_global(&globals[{slot}], true, {at});")
+            }
+            OpCode::Print => format!("rt_print(POP(), {at});"),
+            OpCode::Pop => "sp--;".to_string(),
+            OpCode::Jump(offset) => format!("goto l{};", target(i, offset)),
+            OpCode::JumpIfFalse(offset) => {
+                format!("if (rt_is_falsey(PEEK())) goto l{};", target(i, offset))
+            }
+            OpCode::NoMatch => format!("rt_no_match({at});"),
+            OpCode::Return => "return 0;".to_string(),
+        };
+        writeln!(c, "    {statement}").unwrap();
+    }
+    if targets[code.len()] {
+        writeln!(c, "l{}:;", code.len()).unwrap();
+    }
+    c.push_str("}\n");
+    c
+}
+
+/// Returns a C expression making `value`, which fails at `at` if there's no
+/// room for it.
+fn c_value(value: Value, at: &str) -> String {
+    if value.is_nil() {
+        "NIL_VAL".to_string()
+    } else if value.is_bool() {
+        format!("BOOL_VAL({value})")
+    } else if value.try_into_number() == Ok(i64::MIN) {
+        "NUMBER_VAL(INT64_MIN)".to_string()
+    } else if value.is_number() {
+        format!("NUMBER_VAL(INT64_C({value}))")
+    } else {
+        let chars = value.to_string();
+        format!(
+            "STRING_VAL(rt_string({}, {}, {at}))",
+            c_string(&chars),
+            chars.len()
+        )
+    }
+}
+
+/// Quotes `chars` as a C string literal, escaping every byte that isn't
+/// printable ASCII.
+fn c_string(chars: &str) -> String {
+    let mut quoted = String::from("\"");
+    for byte in chars.bytes() {
+        match byte {
+            b' '..=b'~' if !matches!(byte, b'"' | b'\\' | b'?') => quoted.push(byte as char),
+            _ => write!(quoted, "\\{:03o}", byte).unwrap(),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,337 @@
+/* The runtime of scripts compiled by `rlox build`, mirroring the vm's
+ * values, errors and output. Scripts can't loop, so every string they make
+ * is kept until they exit instead of being collected. Long concatenations
+ * are ropes, as in the vm, so that building a string a piece at a time
+ * takes room for each piece rather than for each copy of the whole. */
+#include <inttypes.h>
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* The shortest concatenation made into a rope, as in the vm. */
+#define ROPE_MIN_LEN 64
+
+/* Strings keep the quotes around their characters, as the vm's do. A rope
+ * joins left and right instead of holding characters, and they're only
+ * gathered into a string of its own, kept in flat, once it's compared. */
+typedef struct String {
+    size_t len;
+    struct String *left, *right;
+    struct String *flat;
+    char chars[];
+} String;
+
+typedef enum { VAL_NIL, VAL_BOOL, VAL_NUMBER, VAL_STRING } ValueType;
+
+typedef struct {
+    ValueType type;
+    union {
+        bool boolean;
+        int64_t number;
+        String *string;
+    } as;
+} Value;
+
+typedef struct {
+    const char *name;
+    bool defined;
+    bool constant;
+    Value value;
+} Global;
+
+/* The vm's limit, unless the build was given another with --max-stack. */
+#ifndef STACK_MAX
+#define STACK_MAX (64 * 1024)
+#endif
+
+/* One slot over the limit holds the operand that the fused instructions
+ * adding a constant or a local push. */
+static Value stack[STACK_MAX + 1];
+static Value *sp = stack;
+
+#define NIL_VAL ((Value){VAL_NIL, {.number = 0}})
+#define BOOL_VAL(b) ((Value){VAL_BOOL, {.boolean = (b)}})
+#define NUMBER_VAL(n) ((Value){VAL_NUMBER, {.number = (n)}})
+#define STRING_VAL(s) ((Value){VAL_STRING, {.string = (s)}})
+
+#define PUSH(v) (*sp++ = (v))
+#define POP() (*--sp)
+#define PEEK() (sp[-1])
+
+_Noreturn static void rt_error(int line, int column, const char *format, ...) {
+    va_list args;
+    fflush(stdout);
+    fputs("Error: ", stderr);
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fprintf(stderr, "\n[line %d, column %d] in script\n\n", line, column);
+    exit(1);
+}
+
+/* Pushes a value the vm would push, failing as it does when the stack is
+ * full. */
+static inline void rt_push(Value v, int line, int column) {
+    if (sp == stack + STACK_MAX) {
+        rt_error(line, column, "Stack overflow.");
+    }
+    PUSH(v);
+}
+
+static void *rt_alloc(size_t size, int line, int column) {
+    void *ptr = malloc(size);
+    if (ptr == NULL) {
+        rt_error(line, column, "Out of memory.");
+    }
+    return ptr;
+}
+
+static inline String *rt_string(const char *chars, size_t len, int line, int column) {
+    String *string = rt_alloc(sizeof(String) + len, line, column);
+    string->len = len;
+    string->left = string->right = string->flat = NULL;
+    memcpy(string->chars, chars, len);
+    return string;
+}
+
+static String *rt_rope(String *left, String *right, int line, int column) {
+    String *rope = rt_alloc(sizeof(String), line, column);
+    rope->len = left->len + right->len - 2;
+    rope->left = left;
+    rope->right = right;
+    rope->flat = NULL;
+    return rope;
+}
+
+/* Copies the characters of the rope into chars, which has room for its
+ * len. The pieces are walked with a stack of their own since ropes built a
+ * piece at a time are as deep as they are long. */
+static void rt_gather(String *rope, char *chars, int line, int column) {
+    size_t count = 0, capacity = 64, len = 1;
+    String **pieces = rt_alloc(capacity * sizeof(String *), line, column);
+    pieces[count++] = rope;
+    chars[0] = '"';
+    while (count > 0) {
+        String *piece = pieces[--count];
+        if (piece->flat != NULL) {
+            piece = piece->flat;
+        }
+        if (piece->left == NULL) {
+            /* Leave out the quotes around each piece. */
+            memcpy(chars + len, piece->chars + 1, piece->len - 2);
+            len += piece->len - 2;
+            continue;
+        }
+        if (count + 2 > capacity) {
+            String **grown = realloc(pieces, 2 * capacity * sizeof(String *));
+            if (grown == NULL) {
+                rt_error(line, column, "Out of memory.");
+            }
+            pieces = grown;
+            capacity *= 2;
+        }
+        pieces[count++] = piece->right;
+        pieces[count++] = piece->left;
+    }
+    chars[len] = '"';
+    free(pieces);
+}
+
+/* Returns the string holding the characters of s, gathering them the first
+ * time s is a rope. */
+static String *rt_flatten(String *s, int line, int column) {
+    if (s->left == NULL) {
+        return s;
+    }
+    if (s->flat == NULL) {
+        String *flat = rt_alloc(sizeof(String) + s->len, line, column);
+        flat->len = s->len;
+        flat->left = flat->right = flat->flat = NULL;
+        rt_gather(s, flat->chars, line, column);
+        s->flat = flat;
+    }
+    return s->flat;
+}
+
+static inline bool rt_is_falsey(Value v) {
+    return v.type == VAL_NIL || (v.type == VAL_BOOL && !v.as.boolean);
+}
+
+static inline bool rt_values_equal(Value a, Value b, int line, int column) {
+    if (a.type != b.type) {
+        return false;
+    }
+    switch (a.type) {
+    case VAL_NIL:
+        return true;
+    case VAL_BOOL:
+        return a.as.boolean == b.as.boolean;
+    case VAL_NUMBER:
+        return a.as.number == b.as.number;
+    case VAL_STRING:
+        if (a.as.string->len != b.as.string->len) {
+            return false;
+        }
+        a.as.string = rt_flatten(a.as.string, line, column);
+        b.as.string = rt_flatten(b.as.string, line, column);
+        return memcmp(a.as.string->chars, b.as.string->chars, a.as.string->len) == 0;
+    }
+    return false;
+}
+
+static inline void rt_write(Value v, FILE *out, int line, int column) {
+    switch (v.type) {
+    case VAL_NIL:
+        fputs("nil", out);
+        break;
+    case VAL_BOOL:
+        fputs(v.as.boolean ? "true" : "false", out);
+        break;
+    case VAL_NUMBER:
+        fprintf(out, "%" PRId64, v.as.number);
+        break;
+    case VAL_STRING:
+        if (v.as.string->left == NULL) {
+            fwrite(v.as.string->chars, 1, v.as.string->len, out);
+        } else if (v.as.string->flat != NULL) {
+            fwrite(v.as.string->flat->chars, 1, v.as.string->len, out);
+        } else {
+            /* Printing doesn't keep the characters, as in the vm. */
+            char *chars = rt_alloc(v.as.string->len, line, column);
+            rt_gather(v.as.string, chars, line, column);
+            fwrite(chars, 1, v.as.string->len, out);
+            free(chars);
+        }
+        break;
+    }
+}
+
+static inline void rt_print(Value v, int line, int column) {
+    rt_write(v, stdout, line, column);
+    putchar('\n');
+}
+
+/* Pushes the result of a checked builtin, failing on overflow as the vm
+ * does. */
+#define PUSH_CHECKED(builtin, a, b, line, column)                          \
+    do {                                                                   \
+        int64_t result;                                                    \
+        if (builtin(a, b, &result)) {                                      \
+            rt_error(line, column, "Integer overflow.");                   \
+        }                                                                  \
+        PUSH(NUMBER_VAL(result));                                          \
+    } while (0)
+
+static inline void rt_add(int line, int column) {
+    Value b = POP(), a = POP();
+    if (a.type == VAL_NUMBER && b.type == VAL_NUMBER) {
+        PUSH_CHECKED(__builtin_add_overflow, a.as.number, b.as.number, line, column);
+    } else if (a.type == VAL_STRING && b.type == VAL_STRING) {
+        /* Leave out the closing quote of a and the opening one of b. */
+        size_t a_len = a.as.string->len - 1, b_len = b.as.string->len - 1;
+        if (a_len + b_len >= ROPE_MIN_LEN) {
+            PUSH(STRING_VAL(rt_rope(a.as.string, b.as.string, line, column)));
+            return;
+        }
+        /* Ropes are never this short, so both hold their characters. */
+        String *string = rt_string(a.as.string->chars, a_len + b_len, line, column);
+        memcpy(string->chars + a_len, b.as.string->chars + 1, b_len);
+        PUSH(STRING_VAL(string));
+    } else {
+        rt_error(line, column, "Operands must be two numbers or two strings");
+    }
+}
+
+/* Pops two numbers into a and b, failing if either isn't one. */
+#define NUMBER_OPERANDS(line, column)                                      \
+    Value bv = POP(), av = POP();                                          \
+    if (av.type != VAL_NUMBER || bv.type != VAL_NUMBER) {                  \
+        rt_error(line, column, "Operands must be two numbers.");           \
+    }                                                                      \
+    int64_t a = av.as.number, b = bv.as.number
+
+static inline void rt_sub(int line, int column) {
+    NUMBER_OPERANDS(line, column);
+    PUSH_CHECKED(__builtin_sub_overflow, a, b, line, column);
+}
+
+static inline void rt_mul(int line, int column) {
+    NUMBER_OPERANDS(line, column);
+    PUSH_CHECKED(__builtin_mul_overflow, a, b, line, column);
+}
+
+static inline void rt_div(int line, int column) {
+    NUMBER_OPERANDS(line, column);
+    if (b == 0) {
+        rt_error(line, column, "Division by zero.");
+    }
+    if (a == INT64_MIN && b == -1) {
+        rt_error(line, column, "Integer overflow.");
+    }
+    PUSH(NUMBER_VAL(a / b));
+}
+
+#define COMPARISON(name, op)                                               \
+    static inline void name(int line, int column) {                        \
+        NUMBER_OPERANDS(line, column);                                     \
+        PUSH(BOOL_VAL(a op b));                                            \
+    }
+COMPARISON(rt_greater, >)
+COMPARISON(rt_less, <)
+COMPARISON(rt_greater_equal, >=)
+COMPARISON(rt_less_equal, <=)
+
+static inline void rt_equal(bool equal, int line, int column) {
+    Value b = POP(), a = POP();
+    PUSH(BOOL_VAL(rt_values_equal(a, b, line, column) == equal));
+}
+
+static inline void rt_negate(int line, int column) {
+    Value a = POP();
+    if (a.type != VAL_NUMBER) {
+        rt_error(line, column, "Operand must be a number");
+    }
+    PUSH_CHECKED(__builtin_sub_overflow, (int64_t)0, a.as.number, line, column);
+}
+
+static inline void rt_not(void) {
+    Value a = POP();
+    PUSH(BOOL_VAL(rt_is_falsey(a)));
+}
+
+static inline void rt_define_global(Global *global, bool constant, int line, int column) {
+    if (global->constant) {
+        rt_error(line, column, "Cannot redefine constant '%s'.", global->name);
+    }
+    global->value = POP();
+    global->defined = true;
+    global->constant = constant;
+}
+
+static inline void rt_get_global(Global *global, int line, int column) {
+    if (!global->defined) {
+        rt_error(line, column, "Undefined variable %s.", global->name);
+    }
+    rt_push(global->value, line, column);
+}
+
+static inline void rt_set_global(Global *global, int line, int column) {
+    if (global->constant) {
+        rt_error(line, column, "Cannot assign to constant '%s'.", global->name);
+    }
+    if (!global->defined) {
+        rt_error(line, column, "Undefined variable %s.", global->name);
+    }
+    global->value = PEEK();
+}
+
+static inline void rt_no_match(int line, int column) {
+    fflush(stdout);
+    fputs("Error: No match arm for value ", stderr);
+    rt_write(PEEK(), stderr, line, column);
+    fprintf(stderr, ".\n[line %d, column %d] in script\n\n", line, column);
+    exit(1);
+}
@@ -0,0 +1,288 @@
+//! This module provides `rlox bench`, which times scripts and compares the
+//! results with a baseline saved by an earlier run.
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs,
+    io::{self, ErrorKind},
+    time::{Duration, Instant},
+};
+
+use crate::{error::Error, frame::CallFrame, run_time::RuntimeState, Options};
+
+/// Settings taken from the command line after `bench`.
+pub(crate) struct BenchOptions {
+    scripts: Vec<String>,
+    /// How many timed runs each script gets.
+    runs: usize,
+    /// How many untimed runs come first.
+    warmup: usize,
+    /// A file of results to compare with.
+    baseline: Option<String>,
+    /// A file to save the results in.
+    save: Option<String>,
+    /// The options every script is run with.
+    options: Options,
+}
+
+impl BenchOptions {
+    pub(crate) fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let count = |arg: &str, n: &str| {
+            n.parse::<usize>()
+                .map_err(|_| format!("Invalid count in '{arg}'."))
+        };
+        let (mut runs, mut warmup) = (10, 2);
+        let (mut baseline, mut save) = (None, None);
+        let (mut scripts, mut rest) = (Vec::new(), Vec::new());
+        for arg in args {
+            if let Some(n) = arg.strip_prefix("--runs=") {
+                runs = count(&arg, n)?;
+            } else if let Some(n) = arg.strip_prefix("--warmup=") {
+                warmup = count(&arg, n)?;
+            } else if let Some(file) = arg.strip_prefix("--baseline=") {
+                baseline = Some(file.to_string());
+            } else if let Some(file) = arg.strip_prefix("--save=") {
+                save = Some(file.to_string());
+            } else if arg.starts_with("--") {
+                rest.push(arg);
+            } else {
+                scripts.push(arg);
+            }
+        }
+        if runs == 0 {
+            return Err("There must be at least one run.".to_string());
+        }
+        if scripts.is_empty() {
+            return Err("No scripts to run.".to_string());
+        }
+        Ok(Self {
+            scripts,
+            runs,
+            warmup,
+            baseline,
+            save,
+            options: Options::parse(rest.into_iter())?,
+        })
+    }
+}
+
+/// The times taken by one script's runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Measurement {
+    min: Duration,
+    median: Duration,
+    mean: Duration,
+    /// The number of instructions executed by each run.
+    instructions: u64,
+}
+
+impl Measurement {
+    fn new(mut times: Vec<Duration>, instructions: u64) -> Self {
+        times.sort();
+        Self {
+            min: times[0],
+            median: times[times.len() / 2],
+            mean: times.iter().sum::<Duration>() / times.len() as u32,
+            instructions,
+        }
+    }
+}
+
+impl Display for Measurement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "min {:?}, median {:?}, mean {:?}, {} instructions",
+            self.min, self.median, self.mean, self.instructions
+        )
+    }
+}
+
+/// Times every script, printing a line for each, then saves the results
+/// if asked to.
+pub(crate) fn run(bench: &BenchOptions) -> Result<(), Error> {
+    let baseline = match &bench.baseline {
+        Some(file) => parse_results(&fs::read_to_string(file)?)?,
+        None => BTreeMap::new(),
+    };
+    let mut results = BTreeMap::new();
+    for script in &bench.scripts {
+        let measurement = measure(script, bench)?;
+        print!("{script}: {measurement}");
+        if let Some(base) = baseline.get(script) {
+            let change = measurement.median.as_secs_f64() / base.median.as_secs_f64() - 1.0;
+            print!(", median {:+.1}% from {:?}", change * 100.0, base.median);
+        }
+        println!();
+        results.insert(script.clone(), measurement);
+    }
+    if let Some(file) = &bench.save {
+        fs::write(file, write_results(&results))?;
+    }
+    Ok(())
+}
+
+/// Runs the script `warmup` and then `runs` times, each time in a new vm,
+/// timing everything but compiling it.
+fn measure(script: &str, bench: &BenchOptions) -> Result<Measurement, Error> {
+    let source = fs::read_to_string(script)?;
+    let mut times = Vec::with_capacity(bench.runs);
+    let mut instructions = 0;
+    for run in 0..bench.warmup + bench.runs {
+        let mut heap = bench.options.heap();
+        let mut vm = bench.options.vm(&mut heap);
+        let chunk = bench.options.compile(&source, &mut heap, &mut vm)?;
+        let mut frame = CallFrame::new(&chunk);
+        let mut state = RuntimeState::new(&mut vm, &mut frame);
+        let start = Instant::now();
+        bench.options.run(&mut state)?;
+        if run >= bench.warmup {
+            times.push(start.elapsed());
+        }
+        instructions = state.instructions();
+    }
+    Ok(Measurement::new(times, instructions))
+}
+
+/// Writes results as a JSON object from script to measurement, with the
+/// times in nanoseconds.
+fn write_results(results: &BTreeMap<String, Measurement>) -> String {
+    let entries = results.iter().map(|(script, m)| {
+        format!(
+            "  {}: {{\"min_ns\": {}, \"median_ns\": {}, \"mean_ns\": {}, \"instructions\": {}}}",
+            json_string(script),
+            m.min.as_nanos(),
+            m.median.as_nanos(),
+            m.mean.as_nanos(),
+            m.instructions
+        )
+    });
+    format!("{{\n{}\n}}\n", entries.collect::<Vec<_>>().join(",\n"))
+}
+
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => quoted.extend(['\\', c]),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Reads results written by [`write_results`]. Only as much JSON as that
+/// writes is understood: objects, strings and whole numbers.
+fn parse_results(json: &str) -> io::Result<BTreeMap<String, Measurement>> {
+    let mut reader = JsonReader(json.trim_start());
+    let mut results = BTreeMap::new();
+    for (script, fields) in reader.object(|r| r.object(JsonReader::number))? {
+        let field = |name| fields.get(name).copied().ok_or_else(JsonReader::invalid);
+        let nanos = |name| field(name).map(Duration::from_nanos);
+        let measurement = Measurement {
+            min: nanos("min_ns")?,
+            median: nanos("median_ns")?,
+            mean: nanos("mean_ns")?,
+            instructions: field("instructions")?,
+        };
+        results.insert(script, measurement);
+    }
+    match reader.0 {
+        "" => Ok(results),
+        _ => Err(JsonReader::invalid()),
+    }
+}
+
+/// The rest of a JSON document, with leading whitespace skipped.
+struct JsonReader<'a>(&'a str);
+
+impl<'a> JsonReader<'a> {
+    fn invalid() -> io::Error {
+        io::Error::new(ErrorKind::InvalidData, "Invalid baseline.")
+    }
+    /// Consumes `token` if it's next.
+    fn eat(&mut self, token: char) -> bool {
+        let rest = self.0.strip_prefix(token);
+        if let Some(rest) = rest {
+            self.0 = rest.trim_start();
+        }
+        rest.is_some()
+    }
+    fn expect(&mut self, token: char) -> io::Result<()> {
+        self.eat(token).then_some(()).ok_or_else(Self::invalid)
+    }
+    fn object<T>(
+        &mut self,
+        mut value: impl FnMut(&mut Self) -> io::Result<T>,
+    ) -> io::Result<BTreeMap<String, T>> {
+        let mut entries = BTreeMap::new();
+        self.expect('{')?;
+        if self.eat('}') {
+            return Ok(entries);
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(':')?;
+            entries.insert(key, value(self)?);
+            if self.eat('}') {
+                return Ok(entries);
+            }
+            self.expect(',')?;
+        }
+    }
+    fn string(&mut self) -> io::Result<String> {
+        let mut chars = self.0.strip_prefix('"').ok_or_else(Self::invalid)?.chars();
+        let mut string = String::new();
+        loop {
+            match chars.next().ok_or_else(Self::invalid)? {
+                '"' => break,
+                '\\' => match chars.next() {
+                    Some('u') => {
+                        let code = chars.as_str().get(..4).ok_or_else(Self::invalid)?;
+                        let code = u32::from_str_radix(code, 16).map_err(|_| Self::invalid())?;
+                        string.push(char::from_u32(code).ok_or_else(Self::invalid)?);
+                        chars.nth(3);
+                    }
+                    Some(c @ ('"' | '\\' | '/')) => string.push(c),
+                    _ => return Err(Self::invalid()),
+                },
+                c => string.push(c),
+            }
+        }
+        self.0 = chars.as_str().trim_start();
+        Ok(string)
+    }
+    fn number(&mut self) -> io::Result<u64> {
+        let end = self
+            .0
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.0.len());
+        let number = self.0[..end].parse().map_err(|_| Self::invalid())?;
+        self.0 = self.0[end..].trim_start();
+        Ok(number)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn results_round_trip() {
+        let measurement = Measurement {
+            min: Duration::from_nanos(1),
+            median: Duration::from_micros(2),
+            mean: Duration::from_millis(3),
+            instructions: 4,
+        };
+        let results = BTreeMap::from([
+            ("benches/fib.lox".to_string(), measurement),
+            ("\"odd\\name\"".to_string(), measurement),
+        ]);
+        assert_eq!(results, parse_results(&write_results(&results)).unwrap());
+        assert!(parse_results("{\"a\": {\"min_ns\": 1}}").is_err());
+        assert!(parse_results("{} trailing").is_err());
+    }
+}
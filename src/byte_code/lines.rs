@@ -1,34 +1,47 @@
+use std::fmt::Display;
+
 use crate::frame::pc::PositionCounter;
-#[repr(transparent)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
-struct LineNumber(u8);
-#[repr(transparent)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
-struct Count(u8);
-#[derive(Debug, Clone, Copy)]
-pub(crate) struct LineCell(LineNumber, Count);
 
-impl LineCell {
-    pub(crate) fn new(line: u8) -> Self {
-        Self(LineNumber(line), Count(1))
+/// Where in the source an instruction was compiled from.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Location {
+    pub(crate) line: u32,
+    pub(crate) column: u32,
+}
+
+impl Location {
+    pub(crate) fn new(line: u32, column: u32) -> Self {
+        Self { line, column }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
     }
 }
 
-#[repr(transparent)]
-pub(crate) struct LinesBuilder(Vec<LineCell>);
+/// The bytes from `start` up to the next run's start, all compiled from
+/// `location`.
+#[derive(Debug, Clone, Copy)]
+struct LineRun {
+    start: u32,
+    location: Location,
+}
+
+pub(crate) struct LinesBuilder(Vec<LineRun>);
 
 impl LinesBuilder {
     pub(crate) fn new() -> Self {
         Self(Vec::new())
     }
-    pub(crate) fn push(&mut self, line: u8) {
-        for LineCell(LineNumber(ln), Count(c)) in &mut self.0 {
-            if *ln == line {
-                *c += 1;
-                return;
-            }
+    /// Records that the code from `offset` on was compiled from `location`.
+    pub(crate) fn push(&mut self, offset: usize, location: Location) {
+        if self.0.last().is_some_and(|run| run.location == location) {
+            return;
         }
-        self.0.push(LineCell::new(line));
+        let start = u32::try_from(offset).expect("Too much code in one chunk.");
+        self.0.push(LineRun { start, location });
     }
 
     pub(crate) fn finalize(self) -> Lines {
@@ -36,27 +49,19 @@ impl LinesBuilder {
     }
 }
 
-#[repr(transparent)]
 #[derive(Debug)]
-pub(crate) struct Lines(Vec<LineCell>);
+pub(crate) struct Lines(Box<[LineRun]>);
 
 impl Lines {
-    pub(crate) fn get(&self, pos: PositionCounter) -> Option<u8> {
-        let mut pos = *pos;
-        for LineCell(LineNumber(ln), Count(c)) in &self.0 {
-            if *c > pos as u8 {
-                return Some(*ln);
-            } else {
-                pos -= *c as usize;
-            }
-        }
-        None
+    pub(crate) fn get(&self, pos: PositionCounter) -> Option<Location> {
+        let runs = self.0.partition_point(|run| run.start as usize <= *pos);
+        runs.checked_sub(1).map(|run| self.0[run].location)
     }
 }
 
 impl From<LinesBuilder> for Lines {
     fn from(value: LinesBuilder) -> Self {
-        Self(value.0)
+        Self(value.0.into_boxed_slice())
     }
 }
 #[cfg(test)]
@@ -66,16 +71,24 @@ mod test {
     #[test]
     fn push_test() {
         let mut builder = LinesBuilder::new();
-        builder.push(1);
-        builder.push(1);
-        builder.push(2);
+        builder.push(0, Location::new(1, 1));
+        builder.push(1, Location::new(1, 1));
+        builder.push(2, Location::new(2, 5));
+        builder.push(4, Location::new(1, 1));
+        builder.push(5, Location::new(300, 1));
         let lines = builder.finalize();
 
         let mut pos = PositionCounter::default();
-        assert_eq!(Some(1), lines.get(pos));
+        assert_eq!(Some(Location::new(1, 1)), lines.get(pos));
+        pos = pos + 1;
+        assert_eq!(Some(Location::new(1, 1)), lines.get(pos));
+        pos = pos + 1;
+        assert_eq!(Some(Location::new(2, 5)), lines.get(pos));
+        pos = pos + 1;
+        assert_eq!(Some(Location::new(2, 5)), lines.get(pos));
         pos = pos + 1;
-        assert_eq!(Some(1), lines.get(pos));
+        assert_eq!(Some(Location::new(1, 1)), lines.get(pos));
         pos = pos + 1;
-        assert_eq!(Some(2), lines.get(pos));
+        assert_eq!(Some(300), lines.get(pos).map(|l| l.line));
     }
 }
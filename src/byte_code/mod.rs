@@ -1,13 +1,30 @@
-use crate::{frame::pc::PositionCounter, heap::Object, value::Value};
+use std::{collections::BTreeMap, fmt::Display};
+
+use crate::{compiler::CompilerError, frame::pc::PositionCounter, value::Value};
 pub(crate) mod lines;
 pub(crate) use lines::*;
 pub(crate) mod op_code;
 use op_code::OP_CODE_MAX;
 pub(crate) use op_code::*;
+mod superinstructions;
+pub(crate) use superinstructions::fuse;
+/// The most constants a chunk can hold, as a three byte index addresses.
+pub(crate) const CONSTANTS_MAX: usize = 1 << 24;
+
 pub(crate) struct ChunkBuilder {
     code: Vec<u8>,
     values: Vec<Value>,
+    /// The index of every constant in `values`, so each is stored once.
+    value_indices: BTreeMap<Value, usize>,
     lines: LinesBuilder,
+    /// The number of instructions written so far.
+    count: usize,
+    /// Forward jumps waiting for their target instruction to be written,
+    /// as the target's instruction index, the position of the operand and
+    /// the jump's location.
+    jumps: Vec<(usize, usize, Location)>,
+    /// The first jump over more code than its operand can hold.
+    too_long: Option<Location>,
 }
 
 impl ChunkBuilder {
@@ -15,12 +32,41 @@ impl ChunkBuilder {
         Self {
             code: Vec::new(),
             values: Vec::new(),
+            value_indices: BTreeMap::new(),
             lines: LinesBuilder::new(),
+            count: 0,
+            jumps: Vec::new(),
+            too_long: None,
+        }
+    }
+    /// Points every jump targeting the next instruction at the end of the
+    /// code written so far.
+    fn patch_jumps(&mut self) {
+        let (count, len) = (self.count, self.code.len());
+        for (_, pos, location) in self.jumps.iter().filter(|(target, ..)| *target == count) {
+            let Ok(offset) = u16::try_from(len - pos - 2) else {
+                self.too_long = self.too_long.or(Some(*location));
+                continue;
+            };
+            self.code[*pos..*pos + 2].copy_from_slice(&offset.to_be_bytes());
         }
+        self.jumps.retain(|(target, ..)| *target != count);
     }
-    pub(crate) fn write_byte(mut self, byte: OpCode, line: usize) -> Self {
+    /// Returns the index of `value` in the constant table, adding it if
+    /// it isn't there yet.
+    fn add_constant(&mut self, value: Value) -> usize {
+        *self.value_indices.entry(value).or_insert_with(|| {
+            self.values.push(value);
+            self.values.len() - 1
+        })
+    }
+    pub(crate) fn write_byte(mut self, byte: OpCode, location: Location) -> Self {
+        self.patch_jumps();
+        self.count += 1;
+        self.lines.push(self.code.len(), location);
         match byte {
             OpCode::Return
+            | OpCode::NoMatch
             | OpCode::Add
             | OpCode::Sub
             | OpCode::Mul
@@ -33,26 +79,51 @@ impl ChunkBuilder {
             | OpCode::Equal
             | OpCode::Greater
             | OpCode::Less
+            | OpCode::NotEqual
+            | OpCode::GreaterEqual
+            | OpCode::LessEqual
             | OpCode::Print
-            | OpCode::Pop => {
+            | OpCode::Pop => self.code.push(byte.into()),
+            OpCode::Constant(c) | OpCode::AddConstant(c) | OpCode::SubConstant(c) => {
+                match self.add_constant(c) {
+                    pos @ 0..=0xff => self.code.extend_from_slice(&[byte.into(), pos as u8]),
+                    pos => {
+                        // The parser reports code with more constants.
+                        assert!(pos < CONSTANTS_MAX, "Too many constants in one chunk.");
+                        let [_, pos @ ..] = (pos as u32).to_be_bytes();
+                        self.code.push(OP_CONSTANT_LONG);
+                        self.code.extend_from_slice(&pos);
+                        // Superinstructions only have room for short
+                        // indices, so take them apart again.
+                        match byte {
+                            OpCode::AddConstant(_) => self.code.push(OP_ADD),
+                            OpCode::SubConstant(_) => self.code.push(OP_SUB),
+                            _ => (),
+                        }
+                    }
+                }
+            }
+            OpCode::DefineGlobal(slot)
+            | OpCode::GetGlobal(slot)
+            | OpCode::SetGlobal(slot)
+            | OpCode::SetGlobalPop(slot)
+            | OpCode::DefineConstGlobal(slot) => {
                 self.code.push(byte.into());
-                self.lines.push(line as u8);
+                self.code.extend_from_slice(&slot.to_be_bytes());
             }
-            OpCode::Constant(c) => {
-                self.values.push(c);
-                let pos = self.values.len() as u8 - 1;
+            OpCode::GetLocal(slot)
+            | OpCode::SetLocal(slot)
+            | OpCode::SetLocalPop(slot)
+            | OpCode::AddLocal(slot)
+            | OpCode::SubLocal(slot) => {
                 self.code.push(byte.into());
-                self.lines.push(line as u8);
-                self.code.push(pos);
-                self.lines.push(line as u8);
+                self.code.push(slot);
             }
-            OpCode::DefineGlobal(v) | OpCode::GetGlobal(v) | OpCode::SetGlobal(v) => {
-                self.values.push(Value::Object(Object::from_ptr(&v)));
-                let pos = self.values.len() as u8 - 1;
+            OpCode::Jump(offset) | OpCode::JumpIfFalse(offset) => {
+                self.jumps
+                    .push((self.count + offset as usize, self.code.len() + 1, location));
                 self.code.push(byte.into());
-                self.lines.push(line as u8);
-                self.code.push(pos);
-                self.lines.push(line as u8);
+                self.code.extend_from_slice(&[0xff, 0xff]);
             }
         }
         self
@@ -66,58 +137,143 @@ pub(crate) struct Chunk {
 }
 
 impl Chunk {
+    /// Creates a chunk from code encoded by something other than a
+    /// [`ChunkBuilder`], like the register machine's code generator.
+    pub(crate) fn new(code: Vec<u8>, values: Vec<Value>, lines: Lines) -> Self {
+        Self {
+            code: code.into_boxed_slice(),
+            values: values.into_boxed_slice(),
+            lines,
+        }
+    }
+    /// Decodes the instruction at `pos`, returning it along with its size.
+    /// The vm reads [`Chunk::code`] directly, this is for listings.
     pub(crate) fn get_instruction(&self, pos: PositionCounter) -> (OpCode, PositionCounter) {
-        let get_val_pos = || {
-            let p = self.code[*pos + 1] as usize;
-            let Value::Object(v) = self.values[p] else {
-		    unreachable!()
-		};
-            v
-        };
+        let get_u16 = || u16::from_be_bytes([self.code[*pos + 1], self.code[*pos + 2]]);
+        let get_constant = || self.values[self.code[*pos + 1] as usize];
         let n = self.code[*pos];
         match n {
-            0 | 2..=OP_CODE_MAX => (n.into(), 1.into()),
-            1 => {
-                let p = self.code[*pos + 1] as usize;
-                let v = self.values[p];
-                (OpCode::Constant(v), 2.into())
-            }
-            16 => {
-                let v = get_val_pos();
-                (OpCode::DefineGlobal(v.as_obj()), 2.into())
+            OP_RETURN | OP_ADD..=OP_CODE_MAX | OP_NO_MATCH | OP_NOT_EQUAL..=OP_LESS_EQUAL => {
+                (n.into(), 1.into())
             }
-            17 => {
-                let v = get_val_pos();
-                (OpCode::GetGlobal(v.as_obj()), 2.into())
-            }
-            18 => {
-                let v = get_val_pos();
-                (OpCode::SetGlobal(v.as_obj()), 2.into())
+            OP_CONSTANT => (OpCode::Constant(get_constant()), 2.into()),
+            OP_DEFINE_GLOBAL => (OpCode::DefineGlobal(get_u16()), 3.into()),
+            OP_GET_GLOBAL => (OpCode::GetGlobal(get_u16()), 3.into()),
+            OP_SET_GLOBAL => (OpCode::SetGlobal(get_u16()), 3.into()),
+            OP_SET_GLOBAL_POP => (OpCode::SetGlobalPop(get_u16()), 3.into()),
+            OP_GET_LOCAL => (OpCode::GetLocal(self.code[*pos + 1]), 2.into()),
+            OP_SET_LOCAL => (OpCode::SetLocal(self.code[*pos + 1]), 2.into()),
+            OP_SET_LOCAL_POP => (OpCode::SetLocalPop(self.code[*pos + 1]), 2.into()),
+            OP_ADD_CONSTANT => (OpCode::AddConstant(get_constant()), 2.into()),
+            OP_SUB_CONSTANT => (OpCode::SubConstant(get_constant()), 2.into()),
+            OP_ADD_LOCAL => (OpCode::AddLocal(self.code[*pos + 1]), 2.into()),
+            OP_SUB_LOCAL => (OpCode::SubLocal(self.code[*pos + 1]), 2.into()),
+            OP_DEFINE_CONST_GLOBAL => (OpCode::DefineConstGlobal(get_u16()), 3.into()),
+            OP_JUMP => (OpCode::Jump(get_u16()), 3.into()),
+            OP_JUMP_IF_FALSE => (OpCode::JumpIfFalse(get_u16()), 3.into()),
+            OP_CONSTANT_LONG => {
+                let p = u32::from_be_bytes([
+                    0,
+                    self.code[*pos + 1],
+                    self.code[*pos + 2],
+                    self.code[*pos + 3],
+                ]);
+                (OpCode::Constant(self.values[p as usize]), 4.into())
             }
             _ => unreachable!(),
         }
     }
-    pub(crate) fn get_line(&self, pos: PositionCounter) -> Option<u8> {
+    pub(crate) fn get_location(&self, pos: PositionCounter) -> Option<Location> {
         self.lines.get(pos)
     }
+    pub(crate) fn code(&self) -> &[u8] {
+        &self.code
+    }
+    pub(crate) fn constants(&self) -> &[Value] {
+        &self.values
+    }
 }
-impl From<ChunkBuilder> for Chunk {
-    fn from(value: ChunkBuilder) -> Self {
-        Self {
+/// Lists the chunk's instructions with their offsets and lines.
+impl Display for Chunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut pos = PositionCounter::default();
+        let mut line = None;
+        while *pos < self.code.len() {
+            let (op_code, size) = self.get_instruction(pos);
+            let location = self.get_location(pos).unwrap_or_default();
+            write!(f, "{:04} ", *pos)?;
+            if line == Some(location.line) {
+                write!(f, "   | ")?;
+            } else {
+                write!(f, "{:4} ", location.line)?;
+            }
+            writeln!(f, "{op_code}")?;
+            line = Some(location.line);
+            pos += size;
+        }
+        Ok(())
+    }
+}
+impl TryFrom<ChunkBuilder> for Chunk {
+    type Error = CompilerError;
+    /// Fails if a jump goes over more bytes than its operand holds. The
+    /// parser only limits the number of instructions jumped over.
+    fn try_from(mut value: ChunkBuilder) -> Result<Self, Self::Error> {
+        value.patch_jumps();
+        if let Some(location) = value.too_long {
+            return Err(CompilerError::at_line(
+                "Too much code to jump over.",
+                location.line as usize,
+            ));
+        }
+        Ok(Self {
             code: value.code.into_boxed_slice(),
             values: value.values.into_boxed_slice(),
             lines: value.lines.finalize(),
-        }
+        })
     }
 }
 
-impl FromIterator<(OpCode, usize)> for Chunk {
-    fn from_iter<T: IntoIterator<Item = (OpCode, usize)>>(iter: T) -> Self {
-        iter.into_iter()
-            .fold(
-                ChunkBuilder::new(),
-                |builder: ChunkBuilder, (code, line)| builder.write_byte(code, line),
-            )
-            .into()
+impl FromIterator<(OpCode, Location)> for ChunkBuilder {
+    fn from_iter<T: IntoIterator<Item = (OpCode, Location)>>(iter: T) -> Self {
+        iter.into_iter().fold(
+            ChunkBuilder::new(),
+            |builder: ChunkBuilder, (code, location)| builder.write_byte(code, location),
+        )
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn long_constants() {
+        let chunk = (0..300)
+            .chain(0..300)
+            .map(|n| (OpCode::Constant(n.into()), Location::default()))
+            .collect::<ChunkBuilder>();
+        let chunk = Chunk::try_from(chunk).unwrap();
+        assert_eq!(300, chunk.constants().len());
+
+        let mut pos = PositionCounter::default();
+        for n in (0..300).chain(0..300) {
+            let (op, size) = chunk.get_instruction(pos);
+            let OpCode::Constant(v) = op else {
+                panic!("expected a constant, found {op:?}");
+            };
+            assert_eq!(Ok(n), v.try_into_number());
+            assert_eq!(if n < 256 { 2 } else { 4 }, *size);
+            pos += size;
+        }
+    }
+    #[test]
+    fn jump_over_too_many_bytes() {
+        let code = std::iter::once(OpCode::Jump(30_000))
+            .chain(std::iter::repeat_n(OpCode::GetGlobal(0), 30_000))
+            .chain(std::iter::once(OpCode::Return))
+            .map(|op_code| (op_code, Location::new(1, 1)))
+            .collect::<ChunkBuilder>();
+        let err = Chunk::try_from(code).unwrap_err().to_string();
+        assert!(err.contains("Too much code to jump over."), "{err}");
     }
 }
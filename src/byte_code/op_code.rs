@@ -1,7 +1,6 @@
-use crate::{
-    heap::{ObjPtr, ObjString},
-    value::Value,
-};
+use std::fmt::Display;
+
+use crate::value::Value;
 
 #[derive(Debug, Copy, Clone)]
 pub(crate) enum OpCode {
@@ -21,30 +20,66 @@ pub(crate) enum OpCode {
     Less,
     Print,
     Pop,
-    DefineGlobal(ObjPtr<ObjString>),
-    GetGlobal(ObjPtr<ObjString>),
-    SetGlobal(ObjPtr<ObjString>),
+    /// Global operands are slots in the vm's [`Globals`].
+    ///
+    /// [`Globals`]: crate::globals::Globals
+    DefineGlobal(u16),
+    GetGlobal(u16),
+    SetGlobal(u16),
+    GetLocal(u8),
+    SetLocal(u8),
+    DefineConstGlobal(u16),
+    /// Jumps forward unconditionally. The offset counts instructions when
+    /// emitted by the compiler and bytes once read back from a [`Chunk`].
+    ///
+    /// [`Chunk`]: super::Chunk
+    Jump(u16),
+    /// Like [`OpCode::Jump`] but only taken if the top of the stack is
+    /// falsey. The condition is left on the stack.
+    JumpIfFalse(u16),
+    /// Reports that no arm of a `match` accepted the value on top of the stack.
+    NoMatch,
+    /// The fused forms of `Equal, Not`, `Less, Not` and `Greater, Not`.
+    NotEqual,
+    GreaterEqual,
+    LessEqual,
+    /// Like [`OpCode::SetGlobal`] and [`OpCode::SetLocal`] followed by
+    /// [`OpCode::Pop`].
+    SetGlobalPop(u16),
+    SetLocalPop(u8),
+    /// Adds or subtracts a constant, or a local, from the top of the stack.
+    /// See [`fuse`] for the pairs of instructions these replace.
+    ///
+    /// [`fuse`]: super::fuse
+    AddConstant(Value),
+    SubConstant(Value),
+    AddLocal(u8),
+    SubLocal(u8),
 }
 
 impl From<u8> for OpCode {
+    /// Decodes instructions without operands.
     fn from(value: u8) -> Self {
         match value {
-            0 => Self::Return,
-            1 => Self::Constant(Value::default()),
-            2 => Self::Add,
-            3 => Self::Sub,
-            4 => Self::Mul,
-            5 => Self::Div,
-            6 => Self::Neg,
-            7 => Self::Nil,
-            8 => Self::True,
-            9 => Self::False,
-            10 => Self::Not,
-            11 => OpCode::Equal,
-            12 => OpCode::Greater,
-            13 => OpCode::Less,
-            14 => OpCode::Print,
-            15 => OpCode::Pop,
+            OP_RETURN => Self::Return,
+            OP_ADD => Self::Add,
+            OP_SUB => Self::Sub,
+            OP_MUL => Self::Mul,
+            OP_DIV => Self::Div,
+            OP_NEG => Self::Neg,
+            OP_NIL => Self::Nil,
+            OP_TRUE => Self::True,
+            OP_FALSE => Self::False,
+            OP_NOT => Self::Not,
+            OP_EQUAL => Self::Equal,
+            OP_GREATER => Self::Greater,
+            OP_LESS => Self::Less,
+            OP_PRINT => Self::Print,
+            OP_POP => Self::Pop,
+            OP_NO_MATCH => Self::NoMatch,
+            OP_NOT_EQUAL => Self::NotEqual,
+            OP_GREATER_EQUAL => Self::GreaterEqual,
+            OP_LESS_EQUAL => Self::LessEqual,
             _ => unreachable!(),
         }
     }
@@ -52,26 +87,162 @@ impl From<u8> for OpCode {
 impl From<OpCode> for u8 {
     fn from(value: OpCode) -> Self {
         match value {
-            OpCode::Return => 0,
-            OpCode::Constant(_) => 1,
-            OpCode::Add => 2,
-            OpCode::Sub => 3,
-            OpCode::Mul => 4,
-            OpCode::Div => 5,
-            OpCode::Neg => 6,
-            OpCode::Nil => 7,
-            OpCode::True => 8,
-            OpCode::False => 9,
-            OpCode::Not => 10,
-            OpCode::Equal => 11,
-            OpCode::Greater => 12,
-            OpCode::Less => 13,
-            OpCode::Print => 14,
-            OpCode::Pop => 15,
-            OpCode::DefineGlobal(_) => 16,
-            OpCode::GetGlobal(_) => 17,
-            OpCode::SetGlobal(_) => 18,
+            OpCode::Return => OP_RETURN,
+            OpCode::Constant(_) => OP_CONSTANT,
+            OpCode::Add => OP_ADD,
+            OpCode::Sub => OP_SUB,
+            OpCode::Mul => OP_MUL,
+            OpCode::Div => OP_DIV,
+            OpCode::Neg => OP_NEG,
+            OpCode::Nil => OP_NIL,
+            OpCode::True => OP_TRUE,
+            OpCode::False => OP_FALSE,
+            OpCode::Not => OP_NOT,
+            OpCode::Equal => OP_EQUAL,
+            OpCode::Greater => OP_GREATER,
+            OpCode::Less => OP_LESS,
+            OpCode::Print => OP_PRINT,
+            OpCode::Pop => OP_POP,
+            OpCode::DefineGlobal(_) => OP_DEFINE_GLOBAL,
+            OpCode::GetGlobal(_) => OP_GET_GLOBAL,
+            OpCode::SetGlobal(_) => OP_SET_GLOBAL,
+            OpCode::GetLocal(_) => OP_GET_LOCAL,
+            OpCode::SetLocal(_) => OP_SET_LOCAL,
+            OpCode::DefineConstGlobal(_) => OP_DEFINE_CONST_GLOBAL,
+            OpCode::Jump(_) => OP_JUMP,
+            OpCode::JumpIfFalse(_) => OP_JUMP_IF_FALSE,
+            OpCode::NoMatch => OP_NO_MATCH,
+            OpCode::NotEqual => OP_NOT_EQUAL,
+            OpCode::GreaterEqual => OP_GREATER_EQUAL,
+            OpCode::LessEqual => OP_LESS_EQUAL,
+            OpCode::SetGlobalPop(_) => OP_SET_GLOBAL_POP,
+            OpCode::SetLocalPop(_) => OP_SET_LOCAL_POP,
+            OpCode::AddConstant(_) => OP_ADD_CONSTANT,
+            OpCode::SubConstant(_) => OP_SUB_CONSTANT,
+            OpCode::AddLocal(_) => OP_ADD_LOCAL,
+            OpCode::SubLocal(_) => OP_SUB_LOCAL,
+        }
+    }
+}
+impl Display for OpCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpCode::Return => write!(f, "RETURN"),
+            OpCode::Constant(v) => write!(f, "CONSTANT {v}"),
+            OpCode::Add => write!(f, "ADD"),
+            OpCode::Sub => write!(f, "SUB"),
+            OpCode::Mul => write!(f, "MUL"),
+            OpCode::Div => write!(f, "DIV"),
+            OpCode::Neg => write!(f, "NEG"),
+            OpCode::Nil => write!(f, "NIL"),
+            OpCode::True => write!(f, "TRUE"),
+            OpCode::False => write!(f, "FALSE"),
+            OpCode::Not => write!(f, "NOT"),
+            OpCode::Equal => write!(f, "EQUAL"),
+            OpCode::Greater => write!(f, "GREATER"),
+            OpCode::Less => write!(f, "LESS"),
+            OpCode::Print => write!(f, "PRINT"),
+            OpCode::Pop => write!(f, "POP"),
+            OpCode::DefineGlobal(slot) => write!(f, "DEFINE_GLOBAL {slot}"),
+            OpCode::GetGlobal(slot) => write!(f, "GET_GLOBAL {slot}"),
+            OpCode::SetGlobal(slot) => write!(f, "SET_GLOBAL {slot}"),
+            OpCode::GetLocal(slot) => write!(f, "GET_LOCAL {slot}"),
+            OpCode::SetLocal(slot) => write!(f, "SET_LOCAL {slot}"),
+            OpCode::DefineConstGlobal(slot) => write!(f, "DEFINE_CONST_GLOBAL {slot}"),
+            OpCode::Jump(offset) => write!(f, "JUMP +{offset}"),
+            OpCode::JumpIfFalse(offset) => write!(f, "JUMP_IF_FALSE +{offset}"),
+            OpCode::NoMatch => write!(f, "NO_MATCH"),
+            OpCode::NotEqual => write!(f, "NOT_EQUAL"),
+            OpCode::GreaterEqual => write!(f, "GREATER_EQUAL"),
+            OpCode::LessEqual => write!(f, "LESS_EQUAL"),
+            OpCode::SetGlobalPop(slot) => write!(f, "SET_GLOBAL_POP {slot}"),
+            OpCode::SetLocalPop(slot) => write!(f, "SET_LOCAL_POP {slot}"),
+            OpCode::AddConstant(v) => write!(f, "ADD_CONSTANT {v}"),
+            OpCode::SubConstant(v) => write!(f, "SUB_CONSTANT {v}"),
+            OpCode::AddLocal(slot) => write!(f, "ADD_LOCAL {slot}"),
+            OpCode::SubLocal(slot) => write!(f, "SUB_LOCAL {slot}"),
+        }
+    }
+}
+impl OpCode {
+    /// The number of values the instruction leaves on the stack minus the
+    /// number it consumes.
+    pub(crate) fn stack_effect(&self) -> isize {
+        match self {
+            OpCode::Constant(_)
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::GetGlobal(_)
+            | OpCode::GetLocal(_) => 1,
+            OpCode::Add
+            | OpCode::Sub
+            | OpCode::Mul
+            | OpCode::Div
+            | OpCode::Equal
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::NotEqual
+            | OpCode::GreaterEqual
+            | OpCode::LessEqual
+            | OpCode::Print
+            | OpCode::Pop
+            | OpCode::DefineGlobal(_)
+            | OpCode::DefineConstGlobal(_)
+            | OpCode::SetGlobalPop(_)
+            | OpCode::SetLocalPop(_) => -1,
+            OpCode::Return
+            | OpCode::Neg
+            | OpCode::Not
+            | OpCode::SetGlobal(_)
+            | OpCode::SetLocal(_)
+            | OpCode::Jump(_)
+            | OpCode::JumpIfFalse(_)
+            | OpCode::NoMatch
+            | OpCode::AddConstant(_)
+            | OpCode::SubConstant(_)
+            | OpCode::AddLocal(_)
+            | OpCode::SubLocal(_) => 0,
         }
     }
 }
 pub(super) const OP_CODE_MAX: u8 = 15;
+
+// The bytes instructions are encoded as, which the vm dispatches on.
+pub(crate) const OP_RETURN: u8 = 0;
+pub(crate) const OP_CONSTANT: u8 = 1;
+pub(crate) const OP_ADD: u8 = 2;
+pub(crate) const OP_SUB: u8 = 3;
+pub(crate) const OP_MUL: u8 = 4;
+pub(crate) const OP_DIV: u8 = 5;
+pub(crate) const OP_NEG: u8 = 6;
+pub(crate) const OP_NIL: u8 = 7;
+pub(crate) const OP_TRUE: u8 = 8;
+pub(crate) const OP_FALSE: u8 = 9;
+pub(crate) const OP_NOT: u8 = 10;
+pub(crate) const OP_EQUAL: u8 = 11;
+pub(crate) const OP_GREATER: u8 = 12;
+pub(crate) const OP_LESS: u8 = 13;
+pub(crate) const OP_PRINT: u8 = 14;
+pub(crate) const OP_POP: u8 = 15;
+pub(crate) const OP_DEFINE_GLOBAL: u8 = 16;
+pub(crate) const OP_GET_GLOBAL: u8 = 17;
+pub(crate) const OP_SET_GLOBAL: u8 = 18;
+pub(crate) const OP_GET_LOCAL: u8 = 19;
+pub(crate) const OP_SET_LOCAL: u8 = 20;
+pub(crate) const OP_DEFINE_CONST_GLOBAL: u8 = 21;
+pub(crate) const OP_JUMP: u8 = 22;
+pub(crate) const OP_JUMP_IF_FALSE: u8 = 23;
+pub(crate) const OP_NO_MATCH: u8 = 24;
+/// Encodes [`OpCode::Constant`] with a three byte index, written in place
+/// of the one byte form once a chunk has more than 256 constants.
+pub(crate) const OP_CONSTANT_LONG: u8 = 25;
+pub(crate) const OP_NOT_EQUAL: u8 = 26;
+pub(crate) const OP_GREATER_EQUAL: u8 = 27;
+pub(crate) const OP_LESS_EQUAL: u8 = 28;
+pub(crate) const OP_SET_GLOBAL_POP: u8 = 29;
+pub(crate) const OP_SET_LOCAL_POP: u8 = 30;
+pub(crate) const OP_ADD_CONSTANT: u8 = 31;
+pub(crate) const OP_SUB_CONSTANT: u8 = 32;
+pub(crate) const OP_ADD_LOCAL: u8 = 33;
+pub(crate) const OP_SUB_LOCAL: u8 = 34;
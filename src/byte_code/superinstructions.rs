@@ -0,0 +1,28 @@
+//! Superinstructions do the work of a common pair of instructions in one
+//! dispatch. The compiler's peephole pass replaces every pair listed in
+//! [`fuse`] with its superinstruction, repeatedly, so a fused instruction
+//! can itself be the first half of a pair.
+use super::{Location, OpCode};
+
+/// Returns the instruction doing the work of `first` then `second`, if
+/// there is one. It keeps the location of the half that can fail, so
+/// runtime errors still point at the right operator.
+pub(crate) fn fuse(
+    (first, first_location): (OpCode, Location),
+    (second, second_location): (OpCode, Location),
+) -> Option<(OpCode, Location)> {
+    match (first, second) {
+        (OpCode::Equal, OpCode::Not) => Some((OpCode::NotEqual, first_location)),
+        (OpCode::Less, OpCode::Not) => Some((OpCode::GreaterEqual, first_location)),
+        (OpCode::Greater, OpCode::Not) => Some((OpCode::LessEqual, first_location)),
+        (OpCode::SetGlobal(slot), OpCode::Pop) => {
+            Some((OpCode::SetGlobalPop(slot), first_location))
+        }
+        (OpCode::SetLocal(slot), OpCode::Pop) => Some((OpCode::SetLocalPop(slot), first_location)),
+        (OpCode::Constant(v), OpCode::Add) => Some((OpCode::AddConstant(v), second_location)),
+        (OpCode::Constant(v), OpCode::Sub) => Some((OpCode::SubConstant(v), second_location)),
+        (OpCode::GetLocal(slot), OpCode::Add) => Some((OpCode::AddLocal(slot), second_location)),
+        (OpCode::GetLocal(slot), OpCode::Sub) => Some((OpCode::SubLocal(slot), second_location)),
+        _ => None,
+    }
+}
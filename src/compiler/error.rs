@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-use crate::lexer::{ErrorToken, Token, TokenType};
+use crate::lexer::{ErrorToken, Token};
 #[macro_export]
 macro_rules! error {
     ($parser:expr, $($arg:tt)*) => {
@@ -40,7 +40,15 @@ impl<'a> CompilerError {
     }
 }
 
-impl<'a> Display for CompilerError {
+impl CompilerError {
+    /// Creates an error found after parsing, which belongs to a line rather
+    /// than a token.
+    pub(crate) fn at_line(message: impl ToString, line: usize) -> Self {
+        ErrorToken::new(message, line).into()
+    }
+}
+
+impl Display for CompilerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut message = self.message.clone();
         write!(f, "[line {}] Error", self.line)?;
@@ -56,8 +64,13 @@ impl<'a> Display for CompilerError {
     }
 }
 
-impl<'a> From<ErrorToken> for CompilerError {
+impl From<ErrorToken> for CompilerError {
     fn from(value: ErrorToken) -> Self {
-        todo!()
+        Self {
+            line: value.line,
+            message: value.message.clone(),
+            token: Some(value),
+            from_lexer: true,
+        }
     }
 }
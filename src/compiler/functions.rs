@@ -1,4 +1,4 @@
-use crate::{error as comp_error, cur_matches,byte_code::OpCode, lexer::{Token, TokenType}};
+use crate::{error as comp_error, error_at_current, cur_matches,byte_code::OpCode, lexer::{Token, TokenType}};
 use super::{parse_rule::*, Parser, Precedence, CompilerResult, CompilerError};
 macro_rules! sync {
     ($parser:expr, $err: expr) => {
@@ -8,8 +8,11 @@ macro_rules! sync {
 }
 
 pub(super) fn parse_precedence<'a>(parser: &mut Parser<'a>, prec: Precedence) -> CompilerResult<()> {
+    parser.nested(|parser| parse_operand(parser, prec))
+}
+fn parse_operand<'a>(parser: &mut Parser<'a>, prec: Precedence) -> CompilerResult<()> {
     parser.advance()?;
-    let Some(parse_rule) = parser.map_previous(|t| t.id.get_rule().map(|r| r.prefix).flatten()).flatten() else {
+    let Some(parse_rule) = parser.map_previous(|t| t.id.get_rule().and_then(|r| r.prefix)).flatten() else {
 	comp_error!(parser, "Expect expression.");
     };
     let can_assign = prec <= Precedence::Assignment;
@@ -22,7 +25,7 @@ pub(super) fn parse_precedence<'a>(parser: &mut Parser<'a>, prec: Precedence) ->
     {
         parser.advance()?;
         let infix_rule = parser
-            .map_previous(|t| t.id.get_rule().map(|r| r.infix).flatten())
+            .map_previous(|t| t.id.get_rule().and_then(|r| r.infix))
             .flatten()
             .unwrap();
         infix_rule(parser, can_assign)?;
@@ -33,7 +36,7 @@ pub(super) fn parse_precedence<'a>(parser: &mut Parser<'a>, prec: Precedence) ->
     Ok(())
 }
 pub(super) fn var_declaration<'a>(parser: &mut Parser<'a>) -> CompilerResult<()> {
-    let global = parser.parse_variable("Expect variable name.")?;
+    let global = parser.parse_variable("Expect variable name.", false)?;
     if cur_matches!(parser, Equal) {
         expression(parser)?;
     } else {
@@ -43,21 +46,31 @@ pub(super) fn var_declaration<'a>(parser: &mut Parser<'a>) -> CompilerResult<()>
         TokenType::Semicolon,
         "Expect ';' after variable declaration.",
     )?;
-    parser.define_variable(global);
+    parser.define_variable(global, false);
+    Ok(())
+}
+pub(super) fn const_declaration<'a>(parser: &mut Parser<'a>) -> CompilerResult<()> {
+    let global = parser.parse_variable("Expect constant name.", true)?;
+    parser.advance_if_id(TokenType::Equal, "Expect '=' after constant name.")?;
+    expression(parser)?;
+    parser.advance_if_id(
+        TokenType::Semicolon,
+        "Expect ';' after constant declaration.",
+    )?;
+    parser.define_variable(global, true);
     Ok(())
 }
 pub(super) fn decleration<'a>(parser: &mut Parser<'a>) {
-    if match parser.matches(Some(TokenType::Var)) {
-        Ok(b) => b,
-        Err(err) => {
-            sync!(parser, err);
-            false
-        }
-    } {
-        if let Err(err) = var_declaration(parser) {
-            sync!(parser, err);
-        }
-    } else if let Err(err) = statement(parser) {
+    let result = match parser.matches(Some(TokenType::Var)) {
+        Ok(true) => var_declaration(parser),
+        Ok(false) => match parser.matches(Some(TokenType::Const)) {
+            Ok(true) => const_declaration(parser),
+            Ok(false) => statement(parser),
+            Err(err) => Err(err),
+        },
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
         sync!(parser, err);
     }
 }
@@ -66,12 +79,30 @@ pub(super) fn named_variable<'a>(
     token: Token<'a>,
     can_assign: bool,
 ) -> CompilerResult<()> {
-    let arg = parser.identifier_constant(token);
+    let location = parser.previous_location;
+    let (get_op, set_op, constant) = match parser.resolve_local(token)? {
+        Some(local) => (
+            OpCode::GetLocal(local.slot),
+            OpCode::SetLocal(local.slot),
+            local.constant,
+        ),
+        None => {
+            let slot = parser.global_slot(token)?;
+            (
+                OpCode::GetGlobal(slot),
+                OpCode::SetGlobal(slot),
+                parser.const_globals.contains(token.lexum),
+            )
+        }
+    };
     if can_assign && cur_matches!(parser, Equal) {
+        if constant {
+            comp_error!(parser, "Cannot assign to constant '{}'.", token.lexum);
+        }
         expression(parser)?;
-        parser.emit_byte(OpCode::SetGlobal(arg));
+        parser.emit_byte_at(set_op, location);
     } else {
-        parser.emit_byte(OpCode::GetGlobal(arg));
+        parser.emit_byte_at(get_op, location);
     }
     Ok(())
 }
@@ -91,9 +122,30 @@ pub(super) fn expression_statement<'a>(parser: &mut Parser<'a>) -> CompilerResul
     parser.emit_byte(OpCode::Pop);
     Ok(())
 }
+pub(super) fn block<'a>(parser: &mut Parser<'a>) -> CompilerResult<()> {
+    while !parser.check(Some(TokenType::RightBrace)) && !parser.is_at_end() {
+        decleration(parser);
+    }
+    parser
+        .advance_if_id(TokenType::RightBrace, "Expect '}' after block.")
+        .map(|_| ())
+}
 pub(super) fn statement<'a>(parser: &mut Parser<'a>) -> CompilerResult<()> {
     if cur_matches!(parser, Print) {
         print_statement(parser)?;
+    } else if cur_matches!(parser, LeftBrace) {
+        parser.begin_scope();
+        let result = parser.nested(block);
+        parser.end_scope();
+        result?;
+    } else if cur_matches!(parser, Yield) {
+        // Generators need functions to suspend and a stack per coroutine,
+        // and the vm has neither.
+        comp_error!(parser, "Generators are not supported yet.");
+    } else if cur_matches!(parser, Return) {
+        // Scripts have no functions to return from, or calls for `return`
+        // to make into tail calls.
+        comp_error!(parser, "Cannot return from top-level code.");
     } else {
         expression_statement(parser)?;
     }
@@ -103,11 +155,13 @@ pub(super) fn expression<'a>(parser: &mut Parser<'a>) -> CompilerResult<()> {
     parse_precedence(parser, Precedence::Assignment)
 }
 pub(super) fn number<'a>(parser: &mut Parser<'a>, _: bool) -> CompilerResult<()> {
-    let num = parser
-        .map_previous(|t| t.lexum)
-        .unwrap()
-        .parse::<i64>()
-        .unwrap();
+    let lexum = parser.map_previous(|t| t.lexum).unwrap();
+    if lexum.contains('.') {
+        comp_error!(parser, "Number literal must be an integer.");
+    }
+    let Ok(num) = lexum.parse::<i64>() else {
+        comp_error!(parser, "Number literal is too large.");
+    };
     parser.emit_byte(OpCode::Constant(num.into()));
     Ok(())
 }
@@ -126,10 +180,11 @@ pub(super) fn string<'a>(parser: &mut Parser<'a>, _: bool) -> CompilerResult<()>
 }
 pub(super) fn unary<'a>(parser: &mut Parser<'a>, _: bool) -> CompilerResult<()> {
     let id = parser.map_previous(|t| t.id).unwrap();
+    let location = parser.previous_location;
     parse_precedence(parser, Precedence::Unary)?;
     match id {
-        TokenType::Minus => parser.emit_byte(OpCode::Neg),
-        TokenType::Bang => parser.emit_byte(OpCode::Not),
+        TokenType::Minus => parser.emit_byte_at(OpCode::Neg, location),
+        TokenType::Bang => parser.emit_byte_at(OpCode::Not, location),
         _ => unreachable!(),
     }
     Ok(())
@@ -145,21 +200,152 @@ pub(super) fn literal<'a>(parser: &mut Parser<'a>, _: bool) -> CompilerResult<()
 }
 pub(super) fn binary<'a>(parser: &mut Parser<'a>, _: bool) -> CompilerResult<()> {
     let op_type = parser.map_previous(|t| t.id).unwrap();
+    let location = parser.previous_location;
     let rule = op_type.get_rule().unwrap();
     parse_precedence(parser, rule.precedence + 1)?;
     match op_type {
-        TokenType::Plus => parser.emit_byte(OpCode::Add),
-        TokenType::Minus => parser.emit_byte(OpCode::Sub),
-        TokenType::Star => parser.emit_byte(OpCode::Mul),
-        TokenType::Slash => parser.emit_byte(OpCode::Div),
-        TokenType::BangEqual => parser.emit_bytes(OpCode::Equal, OpCode::Not),
-        TokenType::EqualEqual => parser.emit_byte(OpCode::Equal),
-        TokenType::Greater => parser.emit_byte(OpCode::Greater),
-        TokenType::GreaterEqual => parser.emit_bytes(OpCode::Less, OpCode::Not),
-        TokenType::Less => parser.emit_byte(OpCode::Less),
-        TokenType::LessEqual => parser.emit_bytes(OpCode::Greater, OpCode::Not),
+        TokenType::Plus => parser.emit_byte_at(OpCode::Add, location),
+        TokenType::Minus => parser.emit_byte_at(OpCode::Sub, location),
+        TokenType::Star => parser.emit_byte_at(OpCode::Mul, location),
+        TokenType::Slash => parser.emit_byte_at(OpCode::Div, location),
+        TokenType::BangEqual => parser.emit_bytes_at(OpCode::Equal, OpCode::Not, location),
+        TokenType::EqualEqual => parser.emit_byte_at(OpCode::Equal, location),
+        TokenType::Greater => parser.emit_byte_at(OpCode::Greater, location),
+        TokenType::GreaterEqual => parser.emit_bytes_at(OpCode::Less, OpCode::Not, location),
+        TokenType::Less => parser.emit_byte_at(OpCode::Less, location),
+        TokenType::LessEqual => parser.emit_bytes_at(OpCode::Greater, OpCode::Not, location),
 
         _ => unreachable!(),
     }
     Ok(())
 }
+pub(super) fn dot<'a>(parser: &mut Parser<'a>, _: bool) -> CompilerResult<()> {
+    // Property access, and the lookup caches that would speed it up, wait on
+    // classes and instances, which the vm doesn't have yet.
+    comp_error!(parser, "Properties are not supported yet.");
+}
+/// Compiles the test for a single `match` pattern against the value in
+/// `subject`, returning the jump taken when it fails and whether the pattern
+/// bound the value to a new local.
+fn match_pattern<'a>(
+    parser: &mut Parser<'a>,
+    subject: u8,
+) -> CompilerResult<(Option<usize>, bool)> {
+    let Some(token) = parser.map_current(|t| *t) else {
+        comp_error!(parser, "Expect match pattern.");
+    };
+    match token.id {
+        TokenType::Identifier if token.lexum == "_" => {
+            parser.advance()?;
+            return Ok((None, false));
+        }
+        TokenType::Identifier => {
+            parser.advance()?;
+            if parser.check(Some(TokenType::LeftBrace)) {
+                comp_error!(parser, "Instance patterns are not supported yet.");
+            }
+            parser.add_local(token, false)?;
+            parser.mark_initialized();
+            parser.emit_byte(OpCode::GetLocal(subject));
+            return Ok((None, true));
+        }
+        TokenType::Number
+        | TokenType::String
+        | TokenType::True
+        | TokenType::False
+        | TokenType::Nil => {
+            parser.emit_byte(OpCode::GetLocal(subject));
+            parser.advance()?;
+            match token.id {
+                TokenType::Number => number(parser, false)?,
+                TokenType::String => string(parser, false)?,
+                _ => literal(parser, false)?,
+            }
+        }
+        TokenType::Minus => {
+            parser.emit_byte(OpCode::GetLocal(subject));
+            parser.advance()?;
+            parser.advance_if_id(TokenType::Number, "Expect number after '-' in pattern.")?;
+            number(parser, false)?;
+            parser.emit_byte(OpCode::Neg);
+        }
+        TokenType::LeftBracket => {
+            error_at_current!(parser, "List patterns are not supported yet.")
+        }
+        _ => error_at_current!(parser, "Expect match pattern."),
+    }
+    parser.emit_byte(OpCode::Equal);
+    let jump = parser.emit_jump(OpCode::JumpIfFalse(0));
+    parser.emit_byte(OpCode::Pop);
+    Ok((Some(jump), false))
+}
+/// Compiles the optional guard and the body of a `match` arm, returning the
+/// jump taken when the guard fails.
+fn match_arm<'a>(parser: &mut Parser<'a>) -> CompilerResult<Option<usize>> {
+    let guard_jump = if cur_matches!(parser, If) {
+        expression(parser)?;
+        let jump = parser.emit_jump(OpCode::JumpIfFalse(0));
+        parser.emit_byte(OpCode::Pop);
+        Some(jump)
+    } else {
+        None
+    };
+    parser.advance_if_id(TokenType::Arrow, "Expect '=>' after match pattern.")?;
+    expression(parser)?;
+    Ok(guard_jump)
+}
+pub(super) fn match_expression<'a>(parser: &mut Parser<'a>, _: bool) -> CompilerResult<()> {
+    let location = parser.previous_location;
+    parser.advance_if_id(TokenType::LeftParen, "Expect '(' after 'match'.")?;
+    expression(parser)?;
+    parser.advance_if_id(TokenType::RightParen, "Expect ')' after match value.")?;
+    parser.advance_if_id(TokenType::LeftBrace, "Expect '{' before match arms.")?;
+    let subject = parser.top_slot()?;
+    let mut end_jumps = Vec::new();
+    let mut has_wildcard = false;
+    while !parser.check(Some(TokenType::RightBrace)) && !parser.is_at_end() {
+        let (pattern_jump, binding) = match_pattern(parser, subject)?;
+        let arm = match_arm(parser);
+        if binding {
+            // Move the arm's value into the binding's slot. The binding goes
+            // out of scope even if the arm failed to compile.
+            if arm.is_ok() {
+                parser.emit_byte(OpCode::SetLocal(subject + 1));
+                parser.emit_byte(OpCode::Pop);
+            }
+            parser.locals.pop();
+        }
+        let guard_jump = arm?;
+        has_wildcard |= pattern_jump.is_none() && guard_jump.is_none();
+        end_jumps.push(parser.emit_jump(OpCode::Jump(0)));
+
+        // A failed test leaves its condition, and any binding, on the stack.
+        if pattern_jump.is_some() || guard_jump.is_some() {
+            parser.stack_depth = subject as usize + 2 + binding as usize;
+            for jump in [pattern_jump, guard_jump].into_iter().flatten() {
+                parser.patch_jump(jump)?;
+            }
+            parser.emit_byte(OpCode::Pop);
+            if binding {
+                parser.emit_byte(OpCode::Pop);
+            }
+        }
+        parser.stack_depth = subject as usize + 1;
+        if !cur_matches!(parser, Comma) {
+            break;
+        }
+    }
+    parser.advance_if_id(TokenType::RightBrace, "Expect '}' after match arms.")?;
+    if !has_wildcard {
+        parser.warning(location, "Match has no wildcard arm.");
+        parser.emit_byte_at(OpCode::NoMatch, location);
+    }
+    for jump in end_jumps {
+        parser.patch_jump(jump)?;
+    }
+    // Replace the subject with the value of the arm that matched.
+    parser.stack_depth = subject as usize + 2;
+    parser.emit_byte(OpCode::SetLocal(subject));
+    parser.emit_byte(OpCode::Pop);
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use crate::lexer::Token;
+
+/// The number of locals addressable by a one byte slot operand.
+pub(super) const LOCALS_MAX: usize = u8::MAX as usize + 1;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Local<'a> {
+    pub(super) name: Token<'a>,
+    /// The scope depth the local was declared in, `None` until its
+    /// initializer has been compiled.
+    pub(super) depth: Option<usize>,
+    /// The stack slot holding the local's value.
+    pub(super) slot: u8,
+    pub(super) constant: bool,
+}
+
+impl<'a> Local<'a> {
+    pub(super) fn new(name: Token<'a>, slot: u8, constant: bool) -> Self {
+        Self {
+            name,
+            depth: None,
+            slot,
+            constant,
+        }
+    }
+}
@@ -2,14 +2,101 @@ pub mod error;
 pub(crate) type CompilerResult<T> = Result<T, CompilerError>;
 pub(crate) mod parser;
 mod functions;
+mod local;
+mod optimize;
 mod parse_rule;
 mod precedence;
 
 pub use self::error::*;
 pub(crate) use parser::*;
 use functions::*;
+use local::*;
 use precedence::*;
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{globals::Globals, heap::Heap, test_vm::TestVm};
 
-
-
+    /// Returns the first error compiling `source` reports.
+    fn compile_error(source: &str) -> Option<String> {
+        let err = TestVm::new().compile(source, true).err();
+        err.map(|err| err.to_string())
+    }
+    #[test]
+    fn assign_to_const_local() {
+        let err = compile_error("{ const a = 1; a = 2; }").unwrap();
+        assert!(err.contains("Cannot assign to constant 'a'."), "{err}");
+    }
+    #[test]
+    fn assign_to_const_global() {
+        let err = compile_error("const a = 1; print a; a = 2;").unwrap();
+        assert!(err.contains("Cannot assign to constant 'a'."), "{err}");
+    }
+    #[test]
+    fn assign_to_var() {
+        assert_eq!(None, compile_error("var a = 1; a = 2; { var b = a; b = 3; }"));
+    }
+    #[test]
+    fn number_literal_too_large() {
+        let err = compile_error("print 9223372036854775808;").unwrap();
+        assert!(err.contains("Number literal is too large."), "{err}");
+    }
+    #[test]
+    fn number_literal_not_integer() {
+        let err = compile_error("print 1.5;").unwrap();
+        assert!(err.contains("Number literal must be an integer."), "{err}");
+    }
+    #[test]
+    fn unsupported_match_patterns() {
+        let err = compile_error("print match (1) { [a, b] => a, _ => 0 };").unwrap();
+        assert!(
+            err.contains("List patterns are not supported yet."),
+            "{err}"
+        );
+        let err = compile_error("print match (1) { Point{x, y} => x, _ => 0 };").unwrap();
+        assert!(
+            err.contains("Instance patterns are not supported yet."),
+            "{err}"
+        );
+    }
+    #[test]
+    fn deep_nesting() {
+        for source in [
+            format!("print {}1{};", "(".repeat(20_000), ")".repeat(20_000)),
+            format!("print {}1;", "-".repeat(20_000)),
+            format!("{}{}", "{".repeat(20_000), "}".repeat(20_000)),
+        ] {
+            let err = compile_error(&source).unwrap();
+            assert!(err.contains("Code is nested too deeply."), "{err}");
+        }
+        let source = format!("print {}1{};", "(".repeat(200), ")".repeat(200));
+        assert_eq!(None, compile_error(&source));
+    }
+    #[test]
+    fn unsupported_generators() {
+        let err = compile_error("yield 1;").unwrap();
+        assert!(err.contains("Generators are not supported yet."), "{err}");
+    }
+    #[test]
+    fn top_level_return() {
+        let err = compile_error("return 1;").unwrap();
+        assert!(err.contains("Cannot return from top-level code."), "{err}");
+    }
+    #[test]
+    fn unsupported_properties() {
+        let err = compile_error("var a = 1; print a.b;").unwrap();
+        assert!(err.contains("Properties are not supported yet."), "{err}");
+    }
+    #[test]
+    fn failed_match_arm_drops_binding() {
+        let (mut heap, mut globals) = (Heap::new(), Globals::new());
+        let mut parser = Parser::new(
+            "print match (1) { n => n +, _ => 0 };",
+            heap.allocator(),
+            &mut globals,
+        );
+        assert!(parser.any(|result| result.is_err()));
+        assert!(parser.locals.is_empty());
+    }
+}
@@ -0,0 +1,211 @@
+//! This module provides the optimizations the parser applies to the code
+//! it emits.
+use crate::{
+    byte_code::{fuse, Location, OpCode},
+    heap::ObjString,
+    value::Value,
+};
+
+use super::Parser;
+
+impl<'a> Parser<'a> {
+    /// Returns the constant `back` instructions from the end of the queue,
+    /// unless a jump lands on or before it.
+    fn queued_constant(&self, back: usize) -> Option<Value> {
+        let index = self.que.len().checked_sub(back + 1)?;
+        if index < self.jump_target {
+            return None;
+        }
+        match self.que.get(index) {
+            Some(Ok((OpCode::Constant(v), _))) => Some(*v),
+            _ => None,
+        }
+    }
+    /// Replaces the constant operands of `op_code` with the constant it
+    /// would compute, returning `false` if they aren't all constants or the
+    /// instruction could fail at runtime, which is left for the vm to report.
+    /// Arithmetic is checked like the vm's, so an overflow isn't folded.
+    pub(super) fn fold_constants(&mut self, op_code: OpCode, location: Location) -> bool {
+        if !self.optimize {
+            return false;
+        }
+        let (operands, value) = match op_code {
+            OpCode::Neg | OpCode::Not => {
+                let Some(a) = self.queued_constant(0) else {
+                    return false;
+                };
+                (1, fold_unary(op_code, a))
+            }
+            OpCode::Add
+            | OpCode::Sub
+            | OpCode::Mul
+            | OpCode::Div
+            | OpCode::Equal
+            | OpCode::Greater
+            | OpCode::Less => {
+                let (Some(a), Some(b)) = (self.queued_constant(1), self.queued_constant(0)) else {
+                    return false;
+                };
+                (2, self.fold_binary(op_code, a, b))
+            }
+            _ => return false,
+        };
+        let Some(value) = value else {
+            return false;
+        };
+        self.que.truncate(self.que.len() - operands);
+        self.stack_depth -= operands;
+        self.emit_byte_at(OpCode::Constant(value), location);
+        true
+    }
+    fn fold_binary(&mut self, op_code: OpCode, a: Value, b: Value) -> Option<Value> {
+        if let (Ok(a), Ok(b)) = (a.try_into_number(), b.try_into_number()) {
+            return match op_code {
+                OpCode::Add => Some(a.checked_add(b)?.into()),
+                OpCode::Sub => Some(a.checked_sub(b)?.into()),
+                OpCode::Mul => Some(a.checked_mul(b)?.into()),
+                OpCode::Div => Some(a.checked_div(b)?.into()),
+                OpCode::Equal => Some((a == b).into()),
+                OpCode::Greater => Some((a > b).into()),
+                OpCode::Less => Some((a < b).into()),
+                _ => None,
+            };
+        }
+        Some(match (op_code, a.try_into_object(), b.try_into_object()) {
+            (OpCode::Add, Ok(a), Ok(b)) if a.is_obj::<ObjString>() && b.is_obj::<ObjString>() => {
+                let (a, b) = (a.as_obj::<ObjString>(), b.as_obj::<ObjString>());
+                let (a, b) = (a.as_ref(), b.as_ref());
+                // Strip the closing and opening quotes like the vm does.
+                let result = format!("{}{}", &a[..a.len() - 1], &b[1..]);
+                self.allocator.allocate_string(result).into()
+            }
+            (OpCode::Equal, _, _) => (a == b).into(),
+            _ => return None,
+        })
+    }
+    /// Rewrites the queued program, fusing instructions into the
+    /// superinstructions listed in [`fuse`] and dropping the code after
+    /// unconditional jumps that nothing jumps to. Jumps are then pointed at
+    /// the new positions of their targets.
+    pub(super) fn peephole(&mut self) {
+        if !self.optimize || self.que.iter().any(Result::is_err) {
+            return;
+        }
+        let code: Vec<_> = self.que.drain(..).map(Result::unwrap).collect();
+        // Jumps only go forward, so every jump to an instruction has been
+        // seen by the time it's reached.
+        let mut targets = vec![false; code.len() + 1];
+        // The new index of every instruction, or of the next one kept, and
+        // the old index of the first part of every instruction kept.
+        let mut indices = vec![0; code.len() + 1];
+        let mut kept = Vec::with_capacity(code.len());
+        let mut reachable = true;
+        for (i, &instruction) in code.iter().enumerate() {
+            indices[i] = self.que.len();
+            reachable |= targets[i];
+            if !reachable {
+                continue;
+            }
+            let (op_code, _) = instruction;
+            if let OpCode::Jump(offset) | OpCode::JumpIfFalse(offset) = op_code {
+                targets[i + 1 + offset as usize] = true;
+            }
+            reachable = !matches!(op_code, OpCode::Jump(_));
+
+            // Nothing is fused into an instruction that's jumped to.
+            let (mut instruction, mut start) = (instruction, i);
+            while !targets[start] {
+                let Some(fused) = self
+                    .que
+                    .back()
+                    .and_then(|last| fuse(*last.as_ref().unwrap(), instruction))
+                else {
+                    break;
+                };
+                self.que.pop_back();
+                (instruction, start) = (fused, kept.pop().unwrap());
+            }
+            indices[i] = self.que.len();
+            self.que.push_back(Ok(instruction));
+            kept.push(start);
+        }
+        indices[code.len()] = self.que.len();
+        for (new, old) in kept.into_iter().enumerate() {
+            if let Some(Ok((OpCode::Jump(offset) | OpCode::JumpIfFalse(offset), _))) =
+                self.que.get_mut(new)
+            {
+                *offset = (indices[old + 1 + *offset as usize] - new - 1) as u16;
+            }
+        }
+    }
+}
+
+fn fold_unary(op_code: OpCode, a: Value) -> Option<Value> {
+    match (op_code, a.try_into_number()) {
+        (OpCode::Neg, Ok(a)) => Some(a.checked_neg()?.into()),
+        (OpCode::Not, _) => Some(!a),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{byte_code::OpCode, test_vm::TestVm};
+
+    fn compile(source: &str, optimize: bool) -> Vec<OpCode> {
+        let code = TestVm::new().compile(source, optimize).unwrap();
+        code.into_iter().map(|(op, _)| op).collect()
+    }
+
+    #[test]
+    fn folds_literal_operands() {
+        let code = compile("print 60 * 60 * 24 - -1 >= 86401;", true);
+        assert!(matches!(
+            code[..],
+            [OpCode::Constant(v), OpCode::Print, OpCode::Return] if v.try_into_bool() == Ok(true)
+        ));
+        let code = compile("print \"a\" + \"b\" == \"ab\";", true);
+        assert!(matches!(code[0], OpCode::Constant(v) if v.try_into_bool() == Ok(true)));
+        assert_eq!(
+            13,
+            compile("print 60 * 60 * 24 - -1 >= 86401;", false).len()
+        );
+    }
+    #[test]
+    fn leaves_failing_operations() {
+        let code = compile("print 1 / 0; print 1 + nil;", true);
+        assert!(matches!(code[2], OpCode::Div));
+        assert!(matches!(code[6], OpCode::Add));
+    }
+    #[test]
+    fn fuses_instructions_and_drops_dead_code() {
+        let code = compile("var x = 1; x = 2; print x >= 3;", true);
+        assert!(matches!(code[3], OpCode::SetGlobalPop(0)));
+        assert!(matches!(code[6], OpCode::GreaterEqual));
+        assert_eq!(9, code.len());
+
+        // Nothing after the wildcard arm is reachable, so the last arm goes
+        // and the jump out of the first arm lands on the shorter end.
+        let code = compile("print match (1) { 2 => 3, _ => 4, 5 => 6 };", true);
+        let OpCode::Jump(offset) = code[7] else {
+            panic!("expected a jump, found {:?}", code[7]);
+        };
+        assert!(matches!(code[8 + offset as usize], OpCode::SetLocalPop(0)));
+        assert!(!code
+            .iter()
+            .any(|op_code| matches!(op_code, OpCode::Constant(v) if v.try_into_number() == Ok(6))));
+    }
+    #[test]
+    fn selects_superinstructions() {
+        let code = compile("{ var a = 1; var b = 2; a = a + 1 - b; }", true);
+        assert!(matches!(
+            code[2..6],
+            [
+                OpCode::GetLocal(0),
+                OpCode::AddConstant(one),
+                OpCode::SubLocal(1),
+                OpCode::SetLocalPop(0)
+            ] if one.try_into_number() == Ok(1)
+        ));
+    }
+}
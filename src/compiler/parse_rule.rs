@@ -1,11 +1,13 @@
 use crate::lexer::TokenType;
 
 use super::{
-    binary, grouping, literal, number, string, unary, variable, CompilerResult, Parser, Precedence,
+    binary, dot, grouping, literal, match_expression, number, string, unary, variable,
+    CompilerResult, Parser, Precedence,
 };
 
 pub(super) type ParseFn = fn(&mut Parser, bool) -> CompilerResult<()>;
 
+#[derive(Default)]
 pub(super) struct ParseRule {
     pub(super) prefix: Option<ParseFn>,
     pub(super) infix: Option<ParseFn>,
@@ -22,22 +24,12 @@ impl ParseRule {
     }
 }
 
-impl<'a> Default for ParseRule {
-    fn default() -> Self {
-        Self {
-            prefix: None,
-            infix: None,
-            precedence: Precedence::default(),
-        }
-    }
-}
-
 pub(super) trait GetRule {
-    fn get_rule<'a>(&self) -> Option<ParseRule>;
+    fn get_rule(&self) -> Option<ParseRule>;
 }
 
 impl GetRule for TokenType {
-    fn get_rule<'a>(&self) -> Option<ParseRule> {
+    fn get_rule(&self) -> Option<ParseRule> {
         match self {
             Self::LeftParen => Some(ParseRule {
                 prefix: Some(grouping),
@@ -84,6 +76,15 @@ impl GetRule for TokenType {
                 prefix: Some(variable),
                 ..Default::default()
             }),
+            Self::Dot => Some(ParseRule {
+                infix: Some(dot),
+                precedence: Precedence::Call,
+                ..Default::default()
+            }),
+            Self::Match => Some(ParseRule {
+                prefix: Some(match_expression),
+                ..Default::default()
+            }),
             _ => None,
         }
     }
@@ -1,25 +1,52 @@
-use std::{collections::VecDeque, iter::Peekable};
+use std::{
+    collections::{BTreeSet, HashSet, VecDeque},
+    fmt::Display,
+    iter::Peekable,
+};
 
 use crate::{
-    byte_code::OpCode,
-    error_at_current,
-    heap::{Allocator, ObjPtr, ObjString},
+    byte_code::{Location, OpCode, CONSTANTS_MAX},
+    error as comp_error, error_at_current,
+    globals::Globals,
+    heap::Allocator,
     lexer::{Lexer, Token, TokenType},
+    value::Value,
 };
 
-use super::{decleration, CompilerError, CompilerResult};
+use super::{decleration, CompilerError, CompilerResult, Local, LOCALS_MAX};
+/// How deeply expressions and blocks may nest. Each level is a few native
+/// stack frames, so this keeps deep code from overflowing the stack.
+const NESTING_MAX: usize = 256;
 #[derive(Debug)]
 pub(crate) struct Parser<'a> {
     previous: Option<Token<'a>>,
     current: Option<Token<'a>>,
     lexer: Peekable<Lexer<'a>>,
-    pub(super) que: VecDeque<CompilerResult<(OpCode, usize)>>,
+    pub(super) que: VecDeque<CompilerResult<(OpCode, Location)>>,
     pub(super) allocator: Allocator,
-    line: usize,
+    globals: &'a mut Globals,
+    pub(super) locals: Vec<Local<'a>>,
+    pub(super) scope_depth: usize,
+    /// Names of the globals declared with `const` in this source.
+    pub(super) const_globals: HashSet<&'a str>,
+    /// The number of values the emitted code leaves on the stack.
+    pub(super) stack_depth: usize,
+    /// Every constant emitted, including those folded away since, so the
+    /// chunk is known to have room for the ones that are left.
+    constants: BTreeSet<Value>,
+    /// The locations of the previous and current tokens.
+    pub(super) previous_location: Location,
+    location: Location,
+    /// The queue index the last patched jump lands on, which instructions
+    /// mustn't be optimized across.
+    pub(super) jump_target: usize,
+    pub(super) optimize: bool,
+    /// How many expressions and blocks enclose the one being parsed.
+    nesting: usize,
 }
 
 impl<'a> Iterator for Parser<'a> {
-    type Item = CompilerResult<(OpCode, usize)>;
+    type Item = CompilerResult<(OpCode, Location)>;
     fn next(&mut self) -> Option<Self::Item> {
         if self.previous.is_none() && self.current.is_none() {
             if let Err(err) = self.advance() {
@@ -40,54 +67,202 @@ impl<'a> Iterator for Parser<'a> {
                 decleration(self);
             }
             self.end_compiler();
+            self.peephole();
         }
         self.que.pop_front()
     }
 }
 
-impl<'a> Default for Parser<'a> {
-    fn default() -> Self {
+impl<'a> Parser<'a> {
+    /// Creates a parser resolving global names to slots in `globals`, which
+    /// is shared with the vm that runs the compiled chunk.
+    pub(crate) fn new(source: &'a str, allocator: Allocator, globals: &'a mut Globals) -> Self {
         Self {
             previous: None,
             current: None,
-            lexer: Lexer::new("").peekable(),
-            que: VecDeque::new(),
-            allocator: Allocator::new(std::ptr::null_mut()),
-            line: 0,
-        }
-    }
-}
-
-impl<'a> Parser<'a> {
-    pub(crate) fn new(source: &'a str, allocator: Allocator) -> Self {
-        Self {
             lexer: Lexer::new(source).peekable(),
+            que: VecDeque::new(),
             allocator,
-            ..Default::default()
+            globals,
+            locals: Vec::new(),
+            scope_depth: 0,
+            const_globals: HashSet::new(),
+            stack_depth: 0,
+            constants: BTreeSet::new(),
+            previous_location: Location::default(),
+            location: Location::new(1, 1),
+            jump_target: 0,
+            optimize: true,
+            nesting: 0,
         }
     }
+    /// Sets whether the parser folds constants and optimizes the code it
+    /// emits, which it does by default.
+    pub(crate) fn with_optimizations(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        self
+    }
+    /// Emits `op_code` at the location of the previous token.
     pub(crate) fn emit_byte(&mut self, op_code: OpCode) {
-        self.que.push_back(Ok((op_code, self.line)));
+        self.emit_byte_at(op_code, self.previous_location);
     }
-    pub(crate) fn emit_bytes(&mut self, op_code: OpCode, op_code2: OpCode) {
+    pub(crate) fn emit_byte_at(&mut self, op_code: OpCode, location: Location) {
+        if self.fold_constants(op_code, location) {
+            return;
+        }
+        if let OpCode::Constant(value) = op_code {
+            if self.constants.insert(value) && self.constants.len() == CONSTANTS_MAX + 1 {
+                let line = self.map_previous(|t| t.line).unwrap_or_default();
+                let message = "Too many constants in one chunk.";
+                let err = CompilerError::new(self.previous, message, line);
+                self.que.push_back(Err(err));
+            }
+        }
+        self.stack_depth = self
+            .stack_depth
+            .saturating_add_signed(op_code.stack_effect());
+        self.que.push_back(Ok((op_code, location)));
+    }
+    /// Emits a jump with a placeholder offset, returning its position for
+    /// [`Parser::patch_jump`].
+    pub(crate) fn emit_jump(&mut self, op_code: OpCode) -> usize {
         self.emit_byte(op_code);
-        self.emit_byte(op_code2);
+        self.que.len() - 1
+    }
+    /// Parses with `parse` one level deeper, failing past [`NESTING_MAX`].
+    pub(super) fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> CompilerResult<T>,
+    ) -> CompilerResult<T> {
+        if self.nesting == NESTING_MAX {
+            comp_error!(self, "Code is nested too deeply.");
+        }
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+    /// Points the jump at `index` to the next instruction to be emitted.
+    pub(crate) fn patch_jump(&mut self, index: usize) -> CompilerResult<()> {
+        let Ok(offset) = u16::try_from(self.que.len() - index - 1) else {
+            comp_error!(self, "Too much code to jump over.");
+        };
+        self.jump_target = self.que.len();
+        if let Some(Ok((op_code, _))) = self.que.get_mut(index) {
+            *op_code = match op_code {
+                OpCode::Jump(_) => OpCode::Jump(offset),
+                OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(offset),
+                _ => unreachable!(),
+            };
+        }
+        Ok(())
+    }
+    /// Returns the stack slot of the value on top of the stack.
+    pub(crate) fn top_slot(&self) -> CompilerResult<u8> {
+        match u8::try_from(self.stack_depth - 1) {
+            Ok(slot) => Ok(slot),
+            Err(_) => comp_error!(self, "Too many local variables in scope."),
+        }
+    }
+    pub(crate) fn warning(&self, location: Location, message: impl Display) {
+        eprintln!("[{location}] Warning: {message}");
+    }
+    pub(crate) fn emit_bytes_at(&mut self, op_code: OpCode, op_code2: OpCode, location: Location) {
+        self.emit_byte_at(op_code, location);
+        self.emit_byte_at(op_code2, location);
     }
     pub(crate) fn emit_return(&mut self) {
         self.emit_byte(OpCode::Return);
     }
-    pub(crate) fn define_variable(&mut self, name: ObjPtr<ObjString>) {
-        self.emit_byte(OpCode::DefineGlobal(name));
+    /// Emits the definition of a variable previously returned by
+    /// [`Parser::parse_variable`], `None` standing for a local.
+    pub(crate) fn define_variable(&mut self, global: Option<u16>, constant: bool) {
+        match global {
+            None => self.mark_initialized(),
+            Some(slot) if constant => self.emit_byte(OpCode::DefineConstGlobal(slot)),
+            Some(slot) => self.emit_byte(OpCode::DefineGlobal(slot)),
+        }
     }
-    pub(super) fn identifier_constant(&mut self, name: Token<'a>) -> ObjPtr<ObjString> {
-        self.allocator.allocate_string(name.lexum).as_obj()
+    /// Returns the slot of the global called `name`.
+    pub(super) fn global_slot(&mut self, name: Token<'a>) -> CompilerResult<u16> {
+        let name = self.allocator.allocate_string(name.lexum).as_obj();
+        match self.globals.resolve(name) {
+            Some(slot) => Ok(slot),
+            None => comp_error!(self, "Too many global variables."),
+        }
     }
+    /// Consumes a variable name, returning its slot if it's a global or
+    /// declaring it in the current scope if it's a local.
     pub(crate) fn parse_variable(
         &mut self,
         err_message: impl ToString,
-    ) -> CompilerResult<ObjPtr<ObjString>> {
+        constant: bool,
+    ) -> CompilerResult<Option<u16>> {
         self.advance_if_id(TokenType::Identifier, err_message)?;
-        Ok(self.identifier_constant(self.previous.unwrap()))
+        self.declare_variable(constant)?;
+        if self.scope_depth > 0 {
+            return Ok(None);
+        }
+        let name = self.previous.unwrap();
+        if constant {
+            self.const_globals.insert(name.lexum);
+        }
+        self.global_slot(name).map(Some)
+    }
+    pub(crate) fn declare_variable(&mut self, constant: bool) -> CompilerResult<()> {
+        if self.scope_depth == 0 {
+            return Ok(());
+        }
+        let name = self.previous.unwrap();
+        for local in self.locals.iter().rev() {
+            if local.depth.is_some_and(|d| d < self.scope_depth) {
+                break;
+            }
+            if local.name.lexum == name.lexum {
+                comp_error!(self, "Already a variable with this name in this scope.");
+            }
+        }
+        self.add_local(name, constant)
+    }
+    /// Declares a local living in the next free stack slot.
+    pub(crate) fn add_local(&mut self, name: Token<'a>, constant: bool) -> CompilerResult<()> {
+        if self.stack_depth >= LOCALS_MAX {
+            comp_error!(self, "Too many local variables in scope.");
+        }
+        self.locals
+            .push(Local::new(name, self.stack_depth as u8, constant));
+        Ok(())
+    }
+    pub(crate) fn mark_initialized(&mut self) {
+        if let Some(local) = self.locals.last_mut() {
+            local.depth = Some(self.scope_depth);
+        }
+    }
+    /// Returns the innermost local named `name`.
+    pub(crate) fn resolve_local(&self, name: Token<'a>) -> CompilerResult<Option<Local<'a>>> {
+        for local in self.locals.iter().rev() {
+            if local.name.lexum == name.lexum {
+                if local.depth.is_none() {
+                    comp_error!(self, "Can't read local variable in its own initializer.");
+                }
+                return Ok(Some(*local));
+            }
+        }
+        Ok(None)
+    }
+    pub(crate) fn begin_scope(&mut self) {
+        self.scope_depth += 1;
+    }
+    pub(crate) fn end_scope(&mut self) {
+        self.scope_depth -= 1;
+        while self
+            .locals
+            .last()
+            .is_some_and(|l| l.depth.is_none_or(|d| d > self.scope_depth))
+        {
+            self.emit_byte(OpCode::Pop);
+            self.locals.pop();
+        }
     }
     pub(crate) fn end_compiler(&mut self) {
         self.emit_return();
@@ -99,16 +274,17 @@ impl<'a> Parser<'a> {
         self.current.as_ref().map(func)
     }
     pub(crate) fn is_current<T: FnOnce(&Token<'a>) -> bool>(&self, func: T) -> bool {
-        self.current.as_ref().map_or(false, func)
+        self.current.as_ref().is_some_and(func)
     }
     pub(crate) fn is_at_end(&mut self) -> bool {
         self.current.is_none()
     }
     pub(crate) fn advance(&mut self) -> CompilerResult<Option<Token<'a>>> {
         self.previous = self.current;
+        self.previous_location = self.location;
         let x = match self.lexer.next() {
             Some(Ok(t)) => {
-                self.line = t.line;
+                self.location = Location::new(t.line as u32, t.column as u32);
                 Some(t)
             }
             Some(Err(err)) => return Err(err.into()),
@@ -139,6 +315,8 @@ impl<'a> Parser<'a> {
         Ok(true)
     }
     pub(crate) fn syncronize(&mut self) {
+        // Only the locals are left on the stack between statements.
+        self.stack_depth = self.locals.last().map_or(0, |l| l.slot as usize + 1);
         while self.current.is_some() {
             if self.map_previous(|t| t.id) == Some(TokenType::Semicolon) {
                 return;
@@ -148,6 +326,7 @@ impl<'a> Parser<'a> {
                 TokenType::Class
                 | TokenType::Fun
                 | TokenType::Var
+                | TokenType::Const
                 | TokenType::For
                 | TokenType::If
                 | TokenType::While
@@ -1,4 +1,4 @@
-use crate::byte_code::{Chunk, OpCode};
+use crate::byte_code::Chunk;
 
 use self::pc::PositionCounter;
 
@@ -56,9 +56,4 @@ impl<'a> CallFrame<'a> {
             position_conunter: 0.into(),
         }
     }
-    pub(crate) fn advance_position(&mut self) -> OpCode {
-        let (op, pos) = self.chunk.get_instruction(self.position_conunter);
-        self.position_conunter += pos;
-        op
-    }
 }
@@ -0,0 +1,76 @@
+//! This module provides the table of global variables shared by the
+//! compiler, which resolves their names to slots, and the vm, which reads
+//! and writes them by slot.
+use std::collections::HashMap;
+
+use crate::{
+    heap::{ObjPtr, ObjString},
+    value::Value,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Global {
+    /// Kept for error messages and as a root for the collector.
+    pub(crate) name: ObjPtr<ObjString>,
+    /// `None` until the global has been defined.
+    pub(crate) value: Option<Value>,
+    /// Set once the global has been defined with `const`.
+    pub(crate) constant: bool,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Globals {
+    /// Names are interned, so their pointers identify them.
+    slots: HashMap<ObjPtr<ObjString>, u16>,
+    globals: Vec<Global>,
+}
+
+impl Globals {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+    /// Returns the slot of the global called `name`, adding an undefined
+    /// one if there's none, or `None` if every slot is taken.
+    pub(crate) fn resolve(&mut self, name: ObjPtr<ObjString>) -> Option<u16> {
+        if let Some(slot) = self.slots.get(&name) {
+            return Some(*slot);
+        }
+        let slot = u16::try_from(self.globals.len()).ok()?;
+        self.globals.push(Global {
+            name,
+            value: None,
+            constant: false,
+        });
+        self.slots.insert(name, slot);
+        Some(slot)
+    }
+    #[inline(always)]
+    pub(crate) fn get(&self, slot: u16) -> &Global {
+        &self.globals[slot as usize]
+    }
+    #[inline(always)]
+    pub(crate) fn get_mut(&mut self, slot: u16) -> &mut Global {
+        &mut self.globals[slot as usize]
+    }
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Global> {
+        self.globals.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::heap::Heap;
+
+    #[test]
+    fn names_keep_their_slots() {
+        let mut heap = Heap::new();
+        let mut globals = Globals::new();
+        let a = heap.alloacte_string("a").as_obj();
+        let b = heap.alloacte_string("b").as_obj();
+        assert_eq!(Some(0), globals.resolve(a));
+        assert_eq!(Some(1), globals.resolve(b));
+        assert_eq!(Some(0), globals.resolve(heap.alloacte_string("a").as_obj()));
+        assert!(globals.get(1).value.is_none());
+    }
+}
@@ -1,8 +1,4 @@
-use std::collections::HashMap;
-
-use crate::value::Value;
-
-use super::{Heap, IsObj, ObjPtr, ObjString, Object};
+use super::{Heap, IsObj, Object};
 #[derive(Debug)]
 pub(crate) struct Allocator {
     heap_ptr: *mut Heap,
@@ -27,4 +23,54 @@ impl Allocator {
                 .unwrap()
         }
     }
+    pub(crate) fn find_string(&self, chars: &str) -> Option<Object> {
+        unsafe {
+            self.heap_ptr
+                .as_ref()
+                .and_then(|heap| heap.find_string(chars))
+        }
+    }
+    pub(crate) fn set_interned(&self, rope: Object, string: Object) {
+        unsafe {
+            self.heap_ptr
+                .as_mut()
+                .map(|heap| heap.set_interned(rope, string))
+                .unwrap()
+        }
+    }
+    pub(crate) fn should_collect(&self) -> bool {
+        unsafe {
+            self.heap_ptr
+                .as_ref()
+                .map(|heap| heap.should_collect())
+                .unwrap()
+        }
+    }
+    pub(crate) fn fits(&self, bytes: usize) -> bool {
+        unsafe { self.heap_ptr.as_ref().map(|heap| heap.fits(bytes)).unwrap() }
+    }
+    pub(crate) fn should_collect_young(&self) -> bool {
+        unsafe {
+            self.heap_ptr
+                .as_ref()
+                .map(|heap| heap.should_collect_young())
+                .unwrap()
+        }
+    }
+    pub(crate) fn collect_garbage<F: FnOnce(&mut Heap)>(&self, mark_roots: F) {
+        unsafe {
+            self.heap_ptr
+                .as_mut()
+                .map(|heap| heap.collect_garbage(mark_roots))
+                .unwrap()
+        }
+    }
+    pub(crate) fn collect_young<F: FnOnce(&mut Heap)>(&self, mark_roots: F) {
+        unsafe {
+            self.heap_ptr
+                .as_mut()
+                .map(|heap| heap.collect_young(mark_roots))
+                .unwrap()
+        }
+    }
 }
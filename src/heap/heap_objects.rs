@@ -4,7 +4,7 @@ use std::{
     hash::{Hash, Hasher},
 };
 
-use super::{HeapObject, ObjMetaData, ObjString, ObjType};
+use super::{HeapObject, ObjMetaData, ObjRope, ObjString, ObjType};
 
 pub(crate) trait IsObj {
     fn obj_id() -> ObjType;
@@ -40,7 +40,7 @@ impl<T: IsObj> PartialEq for ObjPtr<T> {
 impl<T: IsObj> Eq for ObjPtr<T> {}
 impl<T: IsObj> Clone for ObjPtr<T> {
     fn clone(&self) -> Self {
-        Self(self.0, self.1)
+        *self
     }
 }
 impl<T: IsObj> Copy for ObjPtr<T> {}
@@ -50,9 +50,6 @@ impl<T: IsObj + Display> Display for ObjPtr<T> {
     }
 }
 impl<T: IsObj> ObjPtr<T> {
-    pub(crate) fn new(obj: *const T, id: &ObjMetaData) -> Self {
-        Self(id, obj)
-    }
     pub(crate) fn from_opaque(ptr: OpaquePtr, id: &ObjMetaData) -> Self {
         Self(id, ptr.0.cast())
     }
@@ -67,31 +64,76 @@ impl<T: IsObj> ObjPtr<T> {
     }
 }
 
+#[cfg(not(feature = "compact-value"))]
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub(crate) struct Object(*const ObjMetaData, OpaquePtr);
+#[cfg(not(feature = "compact-value"))]
 impl Object {
     pub(crate) fn new(obj: &HeapObject) -> Self {
         Self(&obj.meta_data, obj.ptr)
     }
+    pub(crate) fn obj_meta_data(&self) -> &ObjMetaData {
+        unsafe { self.0.as_ref().unwrap() }
+    }
+    fn opaque_ptr(&self) -> OpaquePtr {
+        self.1
+    }
+    pub(crate) fn from_ptr<T: IsObj>(ptr: &ObjPtr<T>) -> Self {
+        Self(ptr.meta_data(), OpaquePtr::new(ptr.to_inner()))
+    }
+}
+
+/// A pointer to the boxed [`HeapObject`] itself, which the heap never moves.
+#[cfg(feature = "compact-value")]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub(crate) struct Object(*const HeapObject);
+#[cfg(feature = "compact-value")]
+impl Object {
+    pub(crate) fn new(obj: &HeapObject) -> Self {
+        Self(obj)
+    }
+    pub(crate) fn obj_meta_data(&self) -> &ObjMetaData {
+        unsafe { &self.0.as_ref().unwrap().meta_data }
+    }
+    fn opaque_ptr(&self) -> OpaquePtr {
+        unsafe { self.0.as_ref().unwrap().ptr }
+    }
+    /// The object's address, which [`Value`](crate::value::Value) keeps.
+    pub(crate) fn addr(self) -> u64 {
+        self.0.expose_provenance() as u64
+    }
+    pub(crate) fn from_addr(addr: u64) -> Self {
+        Self(std::ptr::with_exposed_provenance(addr as usize))
+    }
+    /// Every [`ObjMetaData`] an [`ObjPtr`] points to lives inside a
+    /// [`HeapObject`], so the object is found from its offset.
+    pub(crate) fn from_ptr<T: IsObj>(ptr: &ObjPtr<T>) -> Self {
+        let meta_data: *const ObjMetaData = ptr.meta_data();
+        let offset = std::mem::offset_of!(HeapObject, meta_data);
+        Self(unsafe { meta_data.byte_sub(offset).cast() })
+    }
+}
+
+impl Object {
     pub(crate) fn obj_id(&self) -> ObjType {
-        unsafe { self.0.as_ref().map(|t| t.id).unwrap() }
+        self.obj_meta_data().id
     }
     pub(crate) fn is_obj<T: IsObj>(&self) -> bool {
-        unsafe { T::obj_id() == self.0.as_ref().map(|t| t.id).unwrap() }
+        T::obj_id() == self.obj_meta_data().id
     }
     pub(crate) fn as_obj<T: IsObj>(&self) -> ObjPtr<T> {
-        let (r, id) = unsafe {
-            let meta_data = self.0.as_ref().unwrap();
-            let ptr = self.1;
-            (ptr, meta_data)
-        };
-        ObjPtr::from_opaque(r, id)
+        ObjPtr::from_opaque(self.opaque_ptr(), self.obj_meta_data())
     }
-    pub(crate) fn obj_meta_data(&self) -> &ObjMetaData {
-        unsafe { self.0.as_ref().unwrap() }
+    /// Returns `true` for strings and ropes.
+    pub(crate) fn is_string(&self) -> bool {
+        matches!(self.obj_id(), ObjType::String | ObjType::Rope)
     }
-    pub(crate) fn from_ptr<T: IsObj>(ptr: &ObjPtr<T>) -> Self {
-        Self(ptr.meta_data(), OpaquePtr::new(ptr.to_inner()))
+    /// The length of a string or rope, quotes included.
+    pub(crate) fn string_len(&self) -> usize {
+        match self.obj_id() {
+            ObjType::String => self.as_obj::<ObjString>().as_ref().len(),
+            ObjType::Rope => self.as_obj::<ObjRope>().as_ref().len(),
+        }
     }
 }
 
@@ -101,6 +143,7 @@ impl Display for Object {
             ObjType::String => {
                 write!(f, "{}", self.as_obj::<ObjString>())
             }
+            ObjType::Rope => write!(f, "{}", self.as_obj::<ObjRope>()),
         }
     }
 }
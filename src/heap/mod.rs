@@ -1,45 +1,329 @@
 pub(crate) mod allocator;
 pub(crate) mod heap_objects;
 pub(crate) mod objects;
+pub(crate) mod stats;
+pub(crate) mod table;
 
-use std::collections::{HashMap, LinkedList};
+use std::time::Instant;
 
 pub(crate) use allocator::*;
 pub(crate) use heap_objects::*;
 pub(crate) use objects::*;
+pub(crate) use stats::*;
+pub(crate) use table::*;
 
 use crate::value::Value;
 
+/// How much the heap may grow after a collection before the next one.
+const GC_HEAP_GROW_FACTOR: usize = 2;
+/// The fewest bytes the heap collects at.
+const GC_MIN_THRESHOLD: usize = 1024 * 1024;
+/// How many bytes of new objects the nursery holds before it's collected.
+const GC_NURSERY_SIZE: usize = 256 * 1024;
+
+/// Objects are boxed so their meta data keeps its address while the lists
+/// are swept.
+#[allow(clippy::vec_box)]
+type ObjectList = Vec<Box<HeapObject>>;
+
 pub(crate) struct Heap {
-    strings: HashMap<String, ObjPtr<ObjString>>,
-    objects: LinkedList<HeapObject>,
+    /// Interned strings, held weakly.
+    strings: StringTable,
+    /// Objects that have survived a collection.
+    objects: ObjectList,
+    /// Objects allocated since the last collection.
+    nursery: ObjectList,
+    gray_stack: Vec<Object>,
+    bytes_allocated: usize,
+    nursery_bytes: usize,
+    next_gc: usize,
+    /// Set during a collection of the nursery alone, in which tenured
+    /// objects are assumed to be reachable.
+    collecting_young: bool,
+    /// Tenured ropes that have kept a young interned string since the last
+    /// collection.
+    remembered: Vec<Object>,
+    stats: GcStats,
+    /// The most bytes the vm may allocate, if it's limited.
+    limit: Option<usize>,
 }
 
 impl Heap {
     pub(crate) fn new() -> Self {
         Self {
-            objects: LinkedList::new(),
-            strings: HashMap::new(),
+            objects: Vec::new(),
+            nursery: Vec::new(),
+            strings: StringTable::new(),
+            gray_stack: Vec::new(),
+            bytes_allocated: 0,
+            nursery_bytes: 0,
+            next_gc: GC_MIN_THRESHOLD,
+            collecting_young: false,
+            remembered: Vec::new(),
+            stats: GcStats::default(),
+            limit: None,
+        }
+    }
+    /// Creates a heap the vm may allocate at most `limit` bytes in.
+    /// Objects made by the compiler are counted but never refused.
+    pub(crate) fn with_limit(limit: usize) -> Self {
+        Self {
+            limit: Some(limit),
+            ..Self::new()
         }
     }
     pub(crate) fn allocate_obj<T: IsObj>(&mut self, obj: T) -> Object {
-        let heap_obj = HeapObject::new(obj);
-        let n = {
-            self.objects.push_back(heap_obj);
-            self.objects.back().unwrap()
-        };
-        Object::new(n)
+        let heap_obj = Box::new(HeapObject::new(obj));
+        self.bytes_allocated += heap_obj.size();
+        self.nursery_bytes += heap_obj.size();
+        let obj = Object::new(&heap_obj);
+        self.nursery.push(heap_obj);
+        obj
+    }
+    /// Returns the interned string equal to `chars`, if there is one.
+    pub(crate) fn find_string(&self, chars: &str) -> Option<Object> {
+        let obj = self.strings.find(chars, hash_string(chars))?;
+        Some(Object::from_ptr(&obj))
     }
     pub(crate) fn alloacte_string<T: ToString>(&mut self, string: T) -> Object {
-        let key = string.to_string();
-        if let Some(obj) = self.strings.get(&key) {
-            return Object::from_ptr(obj);
+        let chars = string.to_string();
+        let hash = hash_string(&chars);
+        if let Some(obj) = self.strings.find(&chars, hash) {
+            return Object::from_ptr(&obj);
         }
-        let obj = self.allocate_obj(ObjString::new(key.clone()));
-        _ = self.strings.insert(key, obj.as_obj());
+        let obj = self.allocate_obj(ObjString::with_hash(chars, hash));
+        self.strings.insert(obj.as_obj());
         obj
     }
+    /// Keeps `string` as the interned copy of `rope`. A tenured rope is
+    /// remembered so the next collection of the nursery keeps the string.
+    pub(crate) fn set_interned(&mut self, rope: Object, string: Object) {
+        rope.as_obj::<ObjRope>().as_ref().interned.set(Some(string));
+        if rope.obj_meta_data().tenured.get() && !string.obj_meta_data().tenured.get() {
+            self.remembered.push(rope);
+        }
+    }
     pub(crate) fn allocator(&mut self) -> Allocator {
         Allocator::new(self)
     }
+    /// Returns the live objects by type along with the collector's history.
+    pub(crate) fn stats(&self) -> HeapStats {
+        let mut stats = HeapStats {
+            interned: self.strings.len(),
+            bytes_allocated: self.bytes_allocated,
+            gc: self.stats,
+            ..Default::default()
+        };
+        for obj in self.objects.iter().chain(&self.nursery) {
+            let obj_stats = stats.objects.entry(obj.meta_data.id).or_default();
+            obj_stats.count += 1;
+            obj_stats.bytes += obj.size();
+        }
+        stats
+    }
+    /// Returns `true` once the heap has grown enough to warrant a collection.
+    pub(crate) fn should_collect(&self) -> bool {
+        self.bytes_allocated > self.next_gc
+    }
+    /// Returns `true` if `bytes` more can be allocated without going over
+    /// the limit.
+    pub(crate) fn fits(&self, bytes: usize) -> bool {
+        self.limit
+            .is_none_or(|limit| self.bytes_allocated + bytes <= limit)
+    }
+    /// Returns `true` once the nursery is full.
+    pub(crate) fn should_collect_young(&self) -> bool {
+        self.nursery_bytes > GC_NURSERY_SIZE
+    }
+    /// Frees every object not reachable from the roots marked by `mark_roots`.
+    pub(crate) fn collect_garbage<F: FnOnce(&mut Heap)>(&mut self, mark_roots: F) {
+        let start = Instant::now();
+        // Every survivor is tenured afterwards, strings included.
+        self.remembered.clear();
+        mark_roots(self);
+        self.trace_references();
+        self.sweep();
+        self.next_gc = GC_MIN_THRESHOLD.max(self.bytes_allocated * GC_HEAP_GROW_FACTOR);
+        self.stats.full.record(start.elapsed());
+    }
+    /// Frees the objects in the nursery not reachable from the roots marked
+    /// by `mark_roots`, promoting the rest. Objects only reference ones
+    /// made before them and never change, so a tenured object can't keep a
+    /// young one alive and no write barrier is needed. The one exception is
+    /// the interned string a rope keeps, so the ropes given one by
+    /// [`Heap::set_interned`] since the last collection are the only
+    /// remembered set.
+    pub(crate) fn collect_young<F: FnOnce(&mut Heap)>(&mut self, mark_roots: F) {
+        let start = Instant::now();
+        self.collecting_young = true;
+        mark_roots(self);
+        for rope in std::mem::take(&mut self.remembered) {
+            if let Some(string) = rope.as_obj::<ObjRope>().as_ref().interned() {
+                self.mark_object(string);
+            }
+        }
+        self.trace_references();
+        self.collecting_young = false;
+        self.strings
+            .retain(|obj| obj.meta_data().tenured.get() || obj.meta_data().marked.get());
+        self.sweep_nursery();
+        self.stats.young.record(start.elapsed());
+    }
+    pub(crate) fn mark_value(&mut self, value: Value) {
+        if let Ok(obj) = value.try_into_object() {
+            self.mark_object(obj);
+        }
+    }
+    pub(crate) fn mark_object(&mut self, obj: Object) {
+        let meta_data = obj.obj_meta_data();
+        if meta_data.marked.get() || (self.collecting_young && meta_data.tenured.get()) {
+            return;
+        }
+        meta_data.marked.set(true);
+        self.gray_stack.push(obj);
+    }
+    fn trace_references(&mut self) {
+        while let Some(obj) = self.gray_stack.pop() {
+            self.blacken_object(obj);
+        }
+    }
+    /// Marks every object referenced by `obj`.
+    fn blacken_object(&mut self, obj: Object) {
+        match obj.obj_meta_data().id {
+            ObjType::String => (),
+            ObjType::Rope => {
+                let rope = obj.as_obj::<ObjRope>();
+                for piece in rope.as_ref().pieces() {
+                    self.mark_object(piece);
+                }
+                if let Some(string) = rope.as_ref().interned() {
+                    self.mark_object(string);
+                }
+            }
+        }
+    }
+    fn sweep(&mut self) {
+        // The intern table doesn't keep strings alive.
+        self.strings.retain(|obj| obj.meta_data().marked.get());
+        let mut freed = 0;
+        self.objects.retain(|obj| {
+            let marked = obj.meta_data.marked.replace(false);
+            if !marked {
+                freed += obj.size();
+            }
+            marked
+        });
+        self.bytes_allocated -= freed;
+        self.stats.bytes_freed += freed;
+        self.sweep_nursery();
+    }
+    /// Frees the unmarked objects in the nursery and tenures the rest.
+    fn sweep_nursery(&mut self) {
+        let mut freed = 0;
+        for obj in self.nursery.drain(..) {
+            if obj.meta_data.marked.replace(false) {
+                obj.meta_data.tenured.set(true);
+                self.objects.push(obj);
+            } else {
+                freed += obj.size();
+            }
+        }
+        self.bytes_allocated -= freed;
+        self.stats.bytes_freed += freed;
+        self.nursery_bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn interned(heap: &Heap, chars: &str) -> Option<ObjPtr<ObjString>> {
+        heap.strings.find(chars, hash_string(chars))
+    }
+
+    #[test]
+    fn sweeps_unreachable_objects() {
+        let mut heap = Heap::new();
+        let kept = heap.alloacte_string("kept");
+        heap.alloacte_string("dropped");
+        let size = heap.bytes_allocated;
+        heap.collect_garbage(|heap| heap.mark_object(kept));
+
+        assert_eq!(1, heap.objects.len());
+        assert!(heap.nursery.is_empty());
+        assert!(heap.bytes_allocated < size);
+        assert!(interned(&heap, "kept").is_some());
+        assert!(interned(&heap, "dropped").is_none());
+        assert!(!kept.obj_meta_data().marked.get());
+    }
+    #[test]
+    fn young_collection_keeps_tenured_objects() {
+        let mut heap = Heap::new();
+        heap.alloacte_string("tenured");
+        heap.collect_garbage(|heap| {
+            let obj = Object::from_ptr(&interned(heap, "tenured").unwrap());
+            heap.mark_object(obj);
+        });
+        let young = heap.alloacte_string("young");
+        heap.alloacte_string("garbage");
+        heap.collect_young(|heap| heap.mark_object(young));
+
+        assert_eq!(2, heap.objects.len());
+        assert!(heap.nursery.is_empty());
+        assert!(interned(&heap, "tenured").is_some());
+        assert!(interned(&heap, "garbage").is_none());
+        assert!(young.obj_meta_data().tenured.get());
+        let stats = heap.stats();
+        assert_eq!(1, stats.gc.full.count);
+        assert_eq!(1, stats.gc.young.count);
+        assert!(stats.gc.bytes_freed > 0);
+        assert_eq!(2, stats.interned);
+        assert_eq!(2, stats.objects[&ObjType::String].count);
+        assert_eq!(heap.bytes_allocated, stats.objects[&ObjType::String].bytes);
+    }
+    #[test]
+    fn ropes_keep_their_pieces_alive() {
+        let mut heap = Heap::new();
+        let left = heap.alloacte_string("\"left\"");
+        let right = heap.alloacte_string("\"right\"");
+        let rope = heap.allocate_obj(ObjRope::new(left, right));
+        let rope = heap.allocate_obj(ObjRope::new(rope, right));
+        heap.alloacte_string("\"dropped\"");
+        heap.collect_garbage(|heap| heap.mark_object(rope));
+
+        assert_eq!(4, heap.objects.len());
+        assert_eq!(rope.to_string(), "\"leftrightright\"");
+        assert_eq!(rope.to_string().len(), rope.string_len());
+    }
+    #[test]
+    fn young_collection_keeps_strings_of_tenured_ropes() {
+        let mut heap = Heap::new();
+        let left = heap.alloacte_string("\"left\"");
+        let rope = heap.allocate_obj(ObjRope::new(left, left));
+        heap.collect_garbage(|heap| heap.mark_object(rope));
+        let string = heap.alloacte_string(rope.to_string());
+        heap.set_interned(rope, string);
+        heap.collect_young(|heap| heap.mark_object(rope));
+
+        assert!(string.obj_meta_data().tenured.get());
+        assert!(interned(&heap, "\"leftleft\"").is_some());
+        assert_eq!(Some(string), rope.as_obj::<ObjRope>().as_ref().interned());
+    }
+    #[test]
+    fn limit_counts_live_objects() {
+        let size = HeapObject::string_size(4);
+        let mut heap = Heap::with_limit(size * 2);
+        assert!(heap.fits(size * 2));
+        heap.alloacte_string("live");
+        heap.alloacte_string("dead");
+        assert!(!heap.fits(size));
+
+        heap.collect_garbage(|heap| {
+            let obj = Object::from_ptr(&interned(heap, "live").unwrap());
+            heap.mark_object(obj);
+        });
+        assert!(heap.fits(size));
+        assert!(!heap.fits(size + 1));
+    }
 }
@@ -1,15 +1,28 @@
 //! This module provides concrete implementations of objects.
 extern crate obj_derive;
 use obj_derive::mark_obj;
-use super::{IsObj, ObjPtr, OpaquePtr};
-use std::{fmt::Display, ops::Deref};
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+use super::{IsObj, ObjPtr, Object, OpaquePtr};
+use std::{cell::Cell, fmt::Display, mem::size_of, ops::Deref};
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub(crate) enum ObjType {
     String,
+    Rope,
 }
-#[derive(Copy, Clone)]
+impl Display for ObjType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::String => write!(f, "string"),
+            Self::Rope => write!(f, "rope"),
+        }
+    }
+}
+#[derive(Clone)]
 pub(crate) struct ObjMetaData {
     pub(crate) id: ObjType,
+    /// Set while the collector has found the object reachable.
+    pub(crate) marked: Cell<bool>,
+    /// Set once the object has survived a collection.
+    pub(crate) tenured: Cell<bool>,
 }
 #[derive(Clone)]
 pub(crate) struct HeapObject {
@@ -24,6 +37,11 @@ impl Display for HeapObject {
                 "{}",
                 ObjPtr::<ObjString>::from_opaque(self.ptr, &self.meta_data)
             ),
+            ObjType::Rope => write!(
+                f,
+                "{}",
+                ObjPtr::<ObjRope>::from_opaque(self.ptr, &self.meta_data)
+            ),
         }
     }
 }
@@ -38,37 +56,141 @@ impl Drop for HeapObject {
                     _ = Box::from_raw(obj_ptr);
                 }
             }
+            ObjType::Rope => {
+                let obj_ptr = ObjPtr::<ObjRope>::from_opaque(self.ptr, &self.meta_data)
+                    .to_inner()
+                    .cast_mut();
+                unsafe {
+                    _ = Box::from_raw(obj_ptr);
+                }
+            }
         }
     }
 }
 impl HeapObject {
     pub(crate) fn new<T: IsObj>(obj: T) -> Self {
         Self {
-            meta_data: ObjMetaData { id: T::obj_id() },
+            meta_data: ObjMetaData {
+                id: T::obj_id(),
+                marked: Cell::new(false),
+                tenured: Cell::new(false),
+            },
             ptr: OpaquePtr::new(Box::into_raw(Box::new(obj))),
         }
     }
+    /// The number of bytes owned by the object, including its payload.
+    pub(crate) fn size(&self) -> usize {
+        size_of::<Self>()
+            + match self.meta_data.id {
+                ObjType::String => {
+                    let obj = ObjPtr::<ObjString>::from_opaque(self.ptr, &self.meta_data);
+                    size_of::<ObjString>() + obj.as_ref().chars.capacity()
+                }
+                ObjType::Rope => size_of::<ObjRope>(),
+            }
+    }
+    /// The number of bytes a string with `capacity` bytes of characters
+    /// takes up once allocated.
+    pub(crate) fn string_size(capacity: usize) -> usize {
+        size_of::<Self>() + size_of::<ObjString>() + capacity
+    }
+    /// The number of bytes a rope takes up once allocated.
+    pub(crate) fn rope_size() -> usize {
+        size_of::<Self>() + size_of::<ObjRope>()
+    }
 }
 
-#[repr(transparent)]
 #[derive(Clone, Debug, Default)]
 #[mark_obj(String)]
-pub(crate) struct ObjString(String);
+pub(crate) struct ObjString {
+    chars: String,
+    /// The hash of `chars`, cached for the intern table.
+    hash: u32,
+}
 impl Deref for ObjString {
     type Target = str;
     fn deref(&self) -> &Self::Target {
-        &self.0[..]
+        &self.chars[..]
     }
 }
 impl Display for ObjString {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.chars)
     }
 }
 impl ObjString {
-    pub(crate) fn new(s: impl ToString) -> Self {
-        Self(s.to_string())
+    /// Creates a string whose hash has already been computed.
+    pub(super) fn with_hash(chars: String, hash: u32) -> Self {
+        Self { chars, hash }
+    }
+    pub(crate) fn hash(&self) -> u32 {
+        self.hash
     }
 }
 
-
+/// Two strings, or ropes, joined without copying their characters, which
+/// are only gathered into one string when they're printed or compared.
+/// Comparing a rope interns its characters and keeps the interned string, so
+/// later comparisons are by identity.
+#[derive(Clone, Debug)]
+#[mark_obj(Rope)]
+pub(crate) struct ObjRope {
+    left: Object,
+    right: Object,
+    /// The length of the joined string, quotes included.
+    len: usize,
+    pub(super) interned: Cell<Option<Object>>,
+}
+impl Display for ObjRope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.interned.get() {
+            Some(string) => write!(f, "{string}"),
+            None => write!(f, "{}", self.flatten()),
+        }
+    }
+}
+impl ObjRope {
+    pub(crate) fn new(left: Object, right: Object) -> Self {
+        let len = left.string_len() + right.string_len() - 2;
+        Self {
+            left,
+            right,
+            len,
+            interned: Cell::new(None),
+        }
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+    pub(crate) fn pieces(&self) -> [Object; 2] {
+        [self.left, self.right]
+    }
+    /// The interned string equal to the rope, once it's been compared.
+    pub(crate) fn interned(&self) -> Option<Object> {
+        self.interned.get()
+    }
+    /// Copies the rope's characters into one string. The pieces are walked
+    /// with a stack of their own since ropes built a piece at a time are
+    /// as deep as they are long.
+    pub(crate) fn flatten(&self) -> String {
+        let mut chars = String::with_capacity(self.len);
+        chars.push('"');
+        let mut pieces = vec![self.right, self.left];
+        while let Some(obj) = pieces.pop() {
+            match obj.obj_id() {
+                // Leave out the quotes around each piece.
+                ObjType::String => {
+                    let string = obj.as_obj::<ObjString>();
+                    let string = string.as_ref();
+                    chars.push_str(&string[1..string.len() - 1]);
+                }
+                ObjType::Rope => {
+                    let [left, right] = obj.as_obj::<ObjRope>().as_ref().pieces();
+                    pieces.extend([right, left]);
+                }
+            }
+        }
+        chars.push('"');
+        chars
+    }
+}
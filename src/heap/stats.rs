@@ -0,0 +1,117 @@
+//! This module configures the collector and records how it behaves.
+use std::{collections::BTreeMap, fmt::Display, str::FromStr, time::Duration};
+
+use super::ObjType;
+
+/// The collection strategy the vm drives the heap with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GcMode {
+    /// Every collection marks and sweeps the whole heap.
+    #[default]
+    MarkSweep,
+    /// New objects live in a nursery that is collected on its own, objects
+    /// surviving a collection are promoted and only swept by full ones.
+    Generational,
+}
+
+impl FromStr for GcMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mark-sweep" => Ok(Self::MarkSweep),
+            "generational" => Ok(Self::Generational),
+            _ => Err(format!("Unknown gc mode '{s}'.")),
+        }
+    }
+}
+
+impl Display for GcMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MarkSweep => write!(f, "mark-sweep"),
+            Self::Generational => write!(f, "generational"),
+        }
+    }
+}
+
+/// Pause times of one kind of collection.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct PauseStats {
+    pub(crate) count: usize,
+    pub(crate) total: Duration,
+    pub(crate) max: Duration,
+}
+
+impl PauseStats {
+    pub(crate) fn record(&mut self, pause: Duration) {
+        self.count += 1;
+        self.total += pause;
+        self.max = self.max.max(pause);
+    }
+    pub(crate) fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::ZERO
+        } else {
+            self.total / self.count as u32
+        }
+    }
+}
+
+impl Display for PauseStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} collections, total {:?}, mean {:?}, max {:?}",
+            self.count,
+            self.total,
+            self.mean(),
+            self.max
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct GcStats {
+    /// Collections of the whole heap.
+    pub(crate) full: PauseStats,
+    /// Collections of the nursery alone.
+    pub(crate) young: PauseStats,
+    /// Bytes freed by every collection so far.
+    pub(crate) bytes_freed: usize,
+}
+
+impl Display for GcStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "full: {}", self.full)?;
+        writeln!(f, "young: {}", self.young)?;
+        write!(f, "bytes freed: {}", self.bytes_freed)
+    }
+}
+
+/// Live objects of one type.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ObjStats {
+    pub(crate) count: usize,
+    pub(crate) bytes: usize,
+}
+
+/// A snapshot of what the heap holds and how it has been collected.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct HeapStats {
+    pub(crate) objects: BTreeMap<ObjType, ObjStats>,
+    /// The number of strings in the intern table.
+    pub(crate) interned: usize,
+    pub(crate) bytes_allocated: usize,
+    pub(crate) gc: GcStats,
+}
+
+impl Display for HeapStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (id, stats) in &self.objects {
+            writeln!(f, "{id}: {} objects, {} bytes", stats.count, stats.bytes)?;
+        }
+        writeln!(f, "interned strings: {}", self.interned)?;
+        writeln!(f, "bytes allocated: {}", self.bytes_allocated)?;
+        write!(f, "{}", self.gc)
+    }
+}
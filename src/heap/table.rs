@@ -0,0 +1,146 @@
+//! This module provides the table strings are interned in.
+use super::{ObjPtr, ObjString};
+
+/// The fraction of slots, tombstones included, that may be used before the
+/// table grows.
+const TABLE_MAX_LOAD: f64 = 0.75;
+
+/// Hashes `chars` with 32-bit FNV-1a.
+pub(crate) fn hash_string(chars: &str) -> u32 {
+    chars.bytes().fold(2166136261u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(16777619)
+    })
+}
+
+#[derive(Clone, Copy)]
+enum Entry {
+    Empty,
+    /// A removed entry, which lookups have to probe past.
+    Tombstone,
+    Full(ObjPtr<ObjString>),
+}
+
+/// An open addressing set of strings keyed by their contents. The strings
+/// themselves are the keys, so nothing is stored twice, and their cached
+/// hashes are reused when the table grows.
+pub(crate) struct StringTable {
+    entries: Box<[Entry]>,
+    /// The number of full entries and tombstones.
+    count: usize,
+    len: usize,
+}
+
+impl StringTable {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: Box::new([]),
+            count: 0,
+            len: 0,
+        }
+    }
+    /// Returns the number of strings in the table.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+    /// Returns the interned string equal to `chars`, whose hash is `hash`.
+    pub(crate) fn find(&self, chars: &str, hash: u32) -> Option<ObjPtr<ObjString>> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let mask = self.entries.len() - 1;
+        let mut index = hash as usize & mask;
+        loop {
+            match self.entries[index] {
+                Entry::Empty => return None,
+                Entry::Full(obj) if obj.as_ref().hash() == hash && &obj.as_ref()[..] == chars => {
+                    return Some(obj)
+                }
+                _ => index = (index + 1) & mask,
+            }
+        }
+    }
+    /// Adds `obj`, which mustn't be in the table already.
+    pub(crate) fn insert(&mut self, obj: ObjPtr<ObjString>) {
+        if (self.count + 1) as f64 > self.entries.len() as f64 * TABLE_MAX_LOAD {
+            self.resize();
+        }
+        let index = Self::free_slot(&self.entries, obj.as_ref().hash());
+        if let Entry::Empty = self.entries[index] {
+            self.count += 1;
+        }
+        self.entries[index] = Entry::Full(obj);
+        self.len += 1;
+    }
+    /// Removes every string for which `f` returns `false`.
+    pub(crate) fn retain<F: FnMut(&ObjPtr<ObjString>) -> bool>(&mut self, mut f: F) {
+        for entry in self.entries.iter_mut() {
+            if let Entry::Full(obj) = entry {
+                if !f(obj) {
+                    *entry = Entry::Tombstone;
+                    self.len -= 1;
+                }
+            }
+        }
+    }
+    /// Returns the first slot without a string on the probe sequence of `hash`.
+    fn free_slot(entries: &[Entry], hash: u32) -> usize {
+        let mask = entries.len() - 1;
+        let mut index = hash as usize & mask;
+        while let Entry::Full(_) = entries[index] {
+            index = (index + 1) & mask;
+        }
+        index
+    }
+    /// Rehashes the strings, dropping tombstones on the way. The capacity
+    /// doubles unless the strings fill less than half of it, in which case
+    /// the slots were mostly tombstones and it stays the same.
+    fn resize(&mut self) {
+        let capacity = match self.entries.len() {
+            capacity if self.len * 2 < capacity => capacity,
+            capacity => (capacity * 2).max(8),
+        };
+        let mut entries = vec![Entry::Empty; capacity].into_boxed_slice();
+        for entry in self.entries.iter() {
+            if let Entry::Full(obj) = entry {
+                entries[Self::free_slot(&entries, obj.as_ref().hash())] = Entry::Full(*obj);
+            }
+        }
+        self.entries = entries;
+        self.count = self.len;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::heap::Heap;
+
+    #[test]
+    fn tombstones_keep_probe_sequences() {
+        let mut heap = Heap::new();
+        let mut table = StringTable::new();
+        let strings = (0..32)
+            .map(|n| heap.alloacte_string(n).as_obj::<ObjString>())
+            .collect::<Vec<_>>();
+        strings.iter().for_each(|s| table.insert(*s));
+
+        table.retain(|s| s.as_ref().parse::<u32>().unwrap() % 2 == 0);
+        assert_eq!(16, table.len());
+        for (n, s) in strings.iter().enumerate() {
+            let chars = n.to_string();
+            let found = table.find(&chars, hash_string(&chars));
+            assert_eq!(n % 2 == 0, found == Some(*s));
+        }
+    }
+    #[test]
+    fn churn_keeps_capacity() {
+        let mut heap = Heap::new();
+        let mut table = StringTable::new();
+        for n in 0..1000 {
+            table.insert(heap.alloacte_string(n).as_obj::<ObjString>());
+            table.retain(|s| s.as_ref().parse::<u32>().unwrap() + 4 > n);
+        }
+        assert_eq!(4, table.len());
+        assert_eq!(16, table.entries.len());
+    }
+}
@@ -4,6 +4,8 @@ use std::{
     ops::{ControlFlow, RangeInclusive},
     str::{CharIndices, FromStr},
 };
+
+use unicode_xid::UnicodeXID;
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ErrorToken {
     pub message: String,
@@ -24,6 +26,7 @@ pub(crate) type LexerResult<'a> = Result<Token<'a>, ErrorToken>;
 pub(crate) enum TokenType{
     LeftParen,  RightParen,
     LeftBrace,  RightBrace,
+    LeftBracket,  RightBracket,
     Comma, Dot, Minus,  Plus,
     Semicolon, Slash,  Star,
     // One or two character tokens.
@@ -33,11 +36,12 @@ pub(crate) enum TokenType{
     Less,  LessEqual,
     // Literals.
     Identifier,  String,  Number,
+    Arrow,
     // Keywords.
-    And,  Class,  Else,  False,
-    For,  Fun,  If,  Nil,  Or,
+    And,  Class,  Const,  Else,  False,
+    For,  Fun,  If,  Match,  Nil,  Or,
     Print,  Return,  Super,  This,
-    True,  Var,  While,
+    True,  Var,  While,  Yield,
 
     None
 }
@@ -50,6 +54,8 @@ impl FromStr for TokenType {
             ")" => Self::RightParen,
             "{" => Self::LeftBrace,
             "}" => Self::RightBrace,
+            "[" => Self::LeftBracket,
+            "]" => Self::RightBracket,
             "," => Self::Comma,
             "." => Self::Dot,
             "-" => Self::Minus,
@@ -61,17 +67,20 @@ impl FromStr for TokenType {
             "!=" => Self::BangEqual,
             "=" => Self::Equal,
             "==" => Self::EqualEqual,
+            "=>" => Self::Arrow,
             ">" => Self::Greater,
             ">=" => Self::GreaterEqual,
             "<" => Self::Less,
             "<=" => Self::LessEqual,
             "and" => Self::And,
             "class" => Self::Class,
+            "const" => Self::Const,
             "else" => Self::Else,
             "false" => Self::False,
             "for" => Self::For,
             "fun" => Self::Fun,
             "if" => Self::If,
+            "match" => Self::Match,
             "nil" => Self::Nil,
             "or" => Self::Or,
             "print" => Self::Print,
@@ -81,9 +90,8 @@ impl FromStr for TokenType {
             "true" => Self::True,
             "var" => Self::Var,
             "while" => Self::While,
-            _ if s.chars().peekable().next_if_eq(&'"').is_some()
-                && s.chars().last() == Some('"') =>
-            {
+            "yield" => Self::Yield,
+            _ if s.chars().peekable().next_if_eq(&'"').is_some() && s.ends_with('"') => {
                 Self::String
             }
             _ if s
@@ -108,17 +116,24 @@ pub(crate) struct Token<'a> {
     pub(crate) id: TokenType,
     pub(crate) lexum: &'a str,
     pub(crate) line: usize,
+    /// The column the token starts at, counting characters from 1.
+    pub(crate) column: usize,
 }
 
 impl<'a> Default for Token<'a> {
     fn default() -> Self {
-        Token::new(TokenType::None, "", 0)
+        Token::new(TokenType::None, "", 0, 0)
     }
 }
 
 impl<'a> Token<'a> {
-    pub(crate) fn new(id: TokenType, lexum: &'a str, line: usize) -> Self {
-        Self { id, lexum, line }
+    pub(crate) fn new(id: TokenType, lexum: &'a str, line: usize, column: usize) -> Self {
+        Self {
+            id,
+            lexum,
+            line,
+            column,
+        }
     }
 }
 
@@ -127,6 +142,10 @@ pub(crate) struct Lexer<'a> {
     start_pos: usize,
     at_end: bool,
     pub(crate) line: usize,
+    /// How far into the source columns have been counted, and the column
+    /// found there.
+    scanned: usize,
+    column: usize,
     chars: Peekable<CharIndices<'a>>,
 }
 impl Debug for Lexer<'_> {
@@ -141,6 +160,8 @@ impl<'a> Lexer<'a> {
             source,
             start_pos: 0,
             line: 1,
+            scanned: 0,
+            column: 1,
             at_end: false,
             chars: source.char_indices().peekable(),
         }
@@ -149,6 +170,17 @@ impl<'a> Lexer<'a> {
     fn get_range(&self, top: usize) -> RangeInclusive<usize> {
         RangeInclusive::new(self.start_pos, top)
     }
+    /// Returns the column of the character at `pos`, only counting the
+    /// characters since the last one asked about.
+    fn column(&mut self, pos: usize) -> usize {
+        let skipped = &self.source[self.scanned..pos];
+        self.column = match skipped.rfind('\n') {
+            Some(newline) => skipped[newline + 1..].chars().count() + 1,
+            None => self.column + skipped.chars().count(),
+        };
+        self.scanned = pos;
+        self.column
+    }
 }
 impl<'a> Iterator for Lexer<'a>
 where
@@ -161,32 +193,27 @@ where
         }
         let mut line = self.line;
         while Some(true)
-            == self
-                .chars
-                .peek()
-                .map(|c| match c.1 {
-                    ' ' | '\r' | '\t' => Some(true),
-                    '\n' => {
-                        line += 1;
-                        Some(true)
-                    }
-                    _ => Some(false),
-                })
-                .flatten()
+            == self.chars.peek().map(|c| match c.1 {
+                '\n' => {
+                    line += 1;
+                    true
+                }
+                c => c.is_whitespace(),
+            })
         {
             self.chars.next();
         }
 
-        let Some((cur_pos, ch)) = self.chars.next() else {
-	    return None;
-	};
+        let (cur_pos, ch) = self.chars.next()?;
         self.line = line;
         self.start_pos = cur_pos;
+        let column = self.column(cur_pos);
         let token = match ch {
-            '(' | ')' | '{' | '}' | ',' | '.' | '-' | '+' | ';' | '*' => Token::new(
+            '(' | ')' | '{' | '}' | '[' | ']' | ',' | '.' | '-' | '+' | ';' | '*' => Token::new(
                 self.source[self.get_range(cur_pos)].parse().unwrap(),
                 &self.source[self.get_range(cur_pos)],
                 self.line,
+                column,
             ),
             '!' if self.chars.next_if(|x| x.1 == '=').is_some() => {
                 let range = self.get_range(cur_pos + 1);
@@ -195,6 +222,7 @@ where
                     self.source[range.clone()].parse().unwrap(),
                     &self.source[range],
                     self.line,
+                    column,
                 )
             }
             '>' if self.chars.next_if(|x| x.1 == '=').is_some() => {
@@ -204,6 +232,7 @@ where
                     self.source[range.clone()].parse().unwrap(),
                     &self.source[range],
                     self.line,
+                    column,
                 )
             }
             '<' if self.chars.next_if(|x| x.1 == '=').is_some() => {
@@ -213,15 +242,17 @@ where
                     self.source[range.clone()].parse().unwrap(),
                     &self.source[range],
                     self.line,
+                    column,
                 )
             }
-            '=' if self.chars.next_if(|x| x.1 == '=').is_some() => {
+            '=' if self.chars.next_if(|x| x.1 == '=' || x.1 == '>').is_some() => {
                 let range = self.get_range(cur_pos + 1);
                 self.start_pos += 1;
                 Token::new(
                     self.source[range.clone()].parse().unwrap(),
                     &self.source[range],
                     self.line,
+                    column,
                 )
             }
             '/' if self.chars.next_if(|x| x.1 == '/').is_some() => {
@@ -235,23 +266,40 @@ where
                 self.start_pos = pos + 1;
                 return self.next();
             }
+            '/' if self.chars.next_if(|x| x.1 == '*').is_some() => {
+                // Block comments nest, so track how many are still open.
+                let start_line = self.line;
+                let mut depth = 1;
+                while depth > 0 {
+                    match self.chars.next() {
+                        Some((_, '*')) if self.chars.next_if(|x| x.1 == '/').is_some() => {
+                            depth -= 1
+                        }
+                        Some((_, '/')) if self.chars.next_if(|x| x.1 == '*').is_some() => {
+                            depth += 1
+                        }
+                        Some((_, '\n')) => self.line += 1,
+                        Some(_) => (),
+                        None => {
+                            self.at_end = true;
+                            return Some(Err(ErrorToken::new(
+                                "Unterminated block comment.",
+                                start_line,
+                            )));
+                        }
+                    }
+                }
+                return self.next();
+            }
             '!' | '>' | '<' | '=' | '/' => Token::new(
                 self.source[self.get_range(cur_pos)].parse().unwrap(),
                 &self.source[self.get_range(cur_pos)],
                 self.line,
+                column,
             ),
             '0'..='9' => {
                 let mut pos = self.start_pos;
-                while Some(true)
-                    == self
-                        .chars
-                        .peek()
-                        .map(|ch| match ch.1 {
-                            '0'..='9' | '.' => Some(true),
-                            _ => Some(false),
-                        })
-                        .flatten()
-                {
+                while Some(true) == self.chars.peek().map(|ch| matches!(ch.1, '0'..='9' | '.')) {
                     self.chars.next();
                     pos += 1;
                 }
@@ -261,28 +309,22 @@ where
                     self.source[range.clone()].parse().unwrap(),
                     &self.source[range],
                     self.line,
+                    column,
                 )
             }
-            'a'..='z' | 'A'..='Z' => {
-                let mut s = self.start_pos;
-                while Some(true)
-                    == self
-                        .chars
-                        .peek()
-                        .map(|x| match x.1 {
-                            'a'..='z' | 'A'..='Z' | '_' | '0'..='9' => Some(true),
-                            _ => Some(false),
-                        })
-                        .flatten()
-                {
-                    s += 1;
-                    self.chars.next();
+            c if c == '_' || c.is_xid_start() => {
+                // Identifiers may contain multi-byte characters so track the
+                // byte position of the last one.
+                let mut s = cur_pos + c.len_utf8() - 1;
+                while let Some((pos, c)) = self.chars.next_if(|x| x.1.is_xid_continue()) {
+                    s = pos + c.len_utf8() - 1;
                 }
                 let range = self.get_range(s);
                 Token::new(
                     self.source[range.clone()].parse().unwrap(),
                     &self.source[range],
                     self.line,
+                    column,
                 )
             }
             '"' => {
@@ -307,9 +349,15 @@ where
                     self.source[range.clone()].parse().unwrap(),
                     &self.source[range],
                     self.line,
+                    column,
                 )
             }
-            _ => unreachable!(),
+            c => {
+                return Some(Err(ErrorToken::new(
+                    format!("Unexpected character '{c}'."),
+                    self.line,
+                )))
+            }
         };
         Some(Ok(token))
     }
@@ -321,19 +369,21 @@ mod test {
 
     #[test]
     fn single_char_token() {
-        let source = "() {} , . - + ; * / ";
+        let source = "() {} [] , . - + ; * / ";
         let expected: Vec<Result<Token<'_>, ErrorToken>> = vec![
-            Token::new(TokenType::LeftParen, "(", 1),
-            Token::new(TokenType::RightParen, ")", 1),
-            Token::new(TokenType::LeftBrace, "{", 1),
-            Token::new(TokenType::RightBrace, "}", 1),
-            Token::new(TokenType::Comma, ",", 1),
-            Token::new(TokenType::Dot, ".", 1),
-            Token::new(TokenType::Minus, "-", 1),
-            Token::new(TokenType::Plus, "+", 1),
-            Token::new(TokenType::Semicolon, ";", 1),
-            Token::new(TokenType::Star, "*", 1),
-            Token::new(TokenType::Slash, "/", 1),
+            Token::new(TokenType::LeftParen, "(", 1, 1),
+            Token::new(TokenType::RightParen, ")", 1, 2),
+            Token::new(TokenType::LeftBrace, "{", 1, 4),
+            Token::new(TokenType::RightBrace, "}", 1, 5),
+            Token::new(TokenType::LeftBracket, "[", 1, 7),
+            Token::new(TokenType::RightBracket, "]", 1, 8),
+            Token::new(TokenType::Comma, ",", 1, 10),
+            Token::new(TokenType::Dot, ".", 1, 12),
+            Token::new(TokenType::Minus, "-", 1, 14),
+            Token::new(TokenType::Plus, "+", 1, 16),
+            Token::new(TokenType::Semicolon, ";", 1, 18),
+            Token::new(TokenType::Star, "*", 1, 20),
+            Token::new(TokenType::Slash, "/", 1, 22),
         ]
         .into_iter()
         .map(Ok)
@@ -345,14 +395,14 @@ mod test {
     fn multi_char_token() {
         let source = "!!=>>==<<===";
         let expected: Vec<Result<Token<'_>, ErrorToken>> = vec![
-            Token::new(TokenType::Bang, "!", 1),
-            Token::new(TokenType::BangEqual, "!=", 1),
-            Token::new(TokenType::Greater, ">", 1),
-            Token::new(TokenType::GreaterEqual, ">=", 1),
-            Token::new(TokenType::Equal, "=", 1),
-            Token::new(TokenType::Less, "<", 1),
-            Token::new(TokenType::LessEqual, "<=", 1),
-            Token::new(TokenType::EqualEqual, "==", 1),
+            Token::new(TokenType::Bang, "!", 1, 1),
+            Token::new(TokenType::BangEqual, "!=", 1, 2),
+            Token::new(TokenType::Greater, ">", 1, 4),
+            Token::new(TokenType::GreaterEqual, ">=", 1, 5),
+            Token::new(TokenType::Equal, "=", 1, 7),
+            Token::new(TokenType::Less, "<", 1, 8),
+            Token::new(TokenType::LessEqual, "<=", 1, 9),
+            Token::new(TokenType::EqualEqual, "==", 1, 11),
         ]
         .into_iter()
         .map(Ok)
@@ -363,9 +413,9 @@ mod test {
     #[test]
     fn number() {
         let input = "123 123.456";
-        let test_results = ["123", "123.456"]
+        let test_results = [("123", 1), ("123.456", 5)]
             .into_iter()
-            .map(|x| Token::new(TokenType::Number, x, 1))
+            .map(|(x, column)| Token::new(TokenType::Number, x, 1, column))
             .map(Ok)
             .collect::<Vec<Result<Token, ErrorToken>>>();
         let lexer = Lexer::new(input);
@@ -377,20 +427,46 @@ mod test {
     fn comments() {
         let input = "// this is a comment\n*";
         let mut lexer = Lexer::new(input);
-        assert_eq!(Some(Ok(Token::new(TokenType::Star, "*", 2))), lexer.next())
+        assert_eq!(
+            Some(Ok(Token::new(TokenType::Star, "*", 2, 1))),
+            lexer.next()
+        )
+    }
+    #[test]
+    fn block_comments() {
+        let input = "/* one\ntwo */ * /* outer /* inner\n */ still outer */\n+";
+        let expected = vec![
+            Token::new(TokenType::Star, "*", 2, 8),
+            Token::new(TokenType::Plus, "+", 4, 1),
+        ]
+        .into_iter()
+        .map(Ok)
+        .collect::<Vec<LexerResult>>();
+        assert_eq!(expected, Lexer::new(input).collect::<Vec<_>>());
+    }
+    #[test]
+    fn unterminated_block_comment() {
+        let input = "*\n/* open /* closed */\n";
+        let expected = vec![
+            Ok(Token::new(TokenType::Star, "*", 1, 1)),
+            Err(ErrorToken::new("Unterminated block comment.", 2)),
+        ];
+        assert_eq!(expected, Lexer::new(input).collect::<Vec<_>>());
     }
     #[test]
     fn identifiers() {
         let input =
-            "and class else false for fun if nil or print return super this true var while me";
+            "and class const else false for fun if match nil or print return super this true var while yield me";
         let expexted_token = [
             TokenType::And,
             TokenType::Class,
+            TokenType::Const,
             TokenType::Else,
             TokenType::False,
             TokenType::For,
             TokenType::Fun,
             TokenType::If,
+            TokenType::Match,
             TokenType::Nil,
             TokenType::Or,
             TokenType::Print,
@@ -400,12 +476,18 @@ mod test {
             TokenType::True,
             TokenType::Var,
             TokenType::While,
+            TokenType::Yield,
             TokenType::Identifier,
         ];
+        let mut column = 1;
         let expected = input
-            .split_whitespace()
-            .zip(expexted_token.into_iter())
-            .map(|x| Token::new(x.1, x.0, 1))
+            .split(' ')
+            .zip(expexted_token)
+            .map(|(lexum, id)| {
+                let token = Token::new(id, lexum, 1, column);
+                column += lexum.len() + 1;
+                token
+            })
             .map(Ok)
             .collect::<Vec<LexerResult>>();
         let lexer = Lexer::new(input);
@@ -414,9 +496,53 @@ mod test {
         }
     }
     #[test]
+    fn underscore_identifiers() {
+        let input = "_ _name a_b";
+        let expected = [("_", 1), ("_name", 3), ("a_b", 9)]
+            .into_iter()
+            .map(|(x, column)| Token::new(TokenType::Identifier, x, 1, column))
+            .map(Ok)
+            .collect::<Vec<LexerResult>>();
+        assert_eq!(expected, Lexer::new(input).collect::<Vec<_>>());
+    }
+    #[test]
+    fn arrow() {
+        let input = "=> = ==";
+        let expected = vec![
+            Token::new(TokenType::Arrow, "=>", 1, 1),
+            Token::new(TokenType::Equal, "=", 1, 4),
+            Token::new(TokenType::EqualEqual, "==", 1, 6),
+        ]
+        .into_iter()
+        .map(Ok)
+        .collect::<Vec<LexerResult>>();
+        assert_eq!(expected, Lexer::new(input).collect::<Vec<_>>());
+    }
+    #[test]
+    fn unicode_identifiers() {
+        let input = "café\u{3000}π_2 naïve";
+        let expected = [("café", 1), ("π_2", 6), ("naïve", 10)]
+            .into_iter()
+            .map(|(x, column)| Token::new(TokenType::Identifier, x, 1, column))
+            .map(Ok)
+            .collect::<Vec<LexerResult>>();
+        assert_eq!(expected, Lexer::new(input).collect::<Vec<_>>());
+    }
+    #[test]
+    fn unexpected_characters() {
+        let input = "@ + #\n%";
+        let expected = vec![
+            Err(ErrorToken::new("Unexpected character '@'.", 1)),
+            Ok(Token::new(TokenType::Plus, "+", 1, 3)),
+            Err(ErrorToken::new("Unexpected character '#'.", 1)),
+            Err(ErrorToken::new("Unexpected character '%'.", 2)),
+        ];
+        assert_eq!(expected, Lexer::new(input).collect::<Vec<_>>());
+    }
+    #[test]
     fn string() {
         let input = "\"hello\"";
-        let expected = Token::new(TokenType::String, "\"hello\"", 1);
+        let expected = Token::new(TokenType::String, "\"hello\"", 1, 1);
 
         assert_eq!(Some(Ok(expected)), Lexer::new(input).next());
     }
@@ -1,84 +1,212 @@
 use std::{
     fs::File,
     io::{self, Read, Write},
-    ops::ControlFlow,
     process::exit,
 };
 
+mod aot;
+mod bench;
 mod byte_code;
 mod compiler;
 mod error;
 mod frame;
+mod globals;
 mod heap;
 mod lexer;
+mod register;
 mod run_time;
 mod stack;
+#[cfg(test)]
+mod test_vm;
 mod value;
 
-use byte_code::Chunk;
+use aot::BuildOptions;
+use bench::BenchOptions;
+use byte_code::{Chunk, ChunkBuilder};
 use compiler::{CompilerError, Parser};
 use error::Error;
 use frame::CallFrame;
-use heap::Heap;
-use run_time::{vm::Vm, RuntimeError, RuntimeState};
+use heap::{GcMode, Heap};
+use register::Backend;
+use run_time::{
+    vm::{Vm, VmResult},
+    RuntimeError, RuntimeState,
+};
+use stack::STACK_MAX;
 
-fn main_loop<'a>(vm: &mut Vm, call_frame: &mut CallFrame<'a>) -> Result<(), RuntimeError> {
-    let mut state = RuntimeState::new(vm, call_frame);
-    loop {
-        let op = state.advance_position();
-        match run_time::interpret_instruction(&mut state, op) {
-            ControlFlow::Break(r) => break r,
-            ControlFlow::Continue(()) => continue,
+const USAGE: &str = concat!(
+    "Usage: rlox [--gc=mark-sweep|generational] [--gc-stats] ",
+    "[--max-heap=BYTES] [--max-stack=VALUES] [--disassemble] [--no-optimize] ",
+    "[--backend=stack|register] [script]\n",
+    "       rlox bench [--runs=N] [--warmup=N] [--baseline=FILE] [--save=FILE] ",
+    "[options] script...\n",
+    "       rlox build [--max-stack=VALUES] [--no-optimize] script [-o FILE]"
+);
+
+/// Settings taken from the command line.
+#[derive(Default)]
+struct Options {
+    script: Option<String>,
+    gc_mode: GcMode,
+    gc_stats: bool,
+    /// Print the compiled code before running it.
+    disassemble: bool,
+    /// Compile the code exactly as written.
+    no_optimize: bool,
+    /// The most bytes the script may allocate.
+    max_heap: Option<usize>,
+    /// The most values the vm's stack may hold.
+    max_stack: Option<usize>,
+    /// The machine scripts are compiled for.
+    backend: Backend,
+}
+
+impl Options {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        for arg in args {
+            if let Some(mode) = arg.strip_prefix("--gc=") {
+                options.gc_mode = mode.parse()?;
+            } else if let Some(bytes) = arg.strip_prefix("--max-heap=") {
+                let bytes = bytes
+                    .parse()
+                    .map_err(|_| format!("Invalid heap limit '{bytes}'."))?;
+                options.max_heap = Some(bytes);
+            } else if let Some(values) = arg.strip_prefix("--max-stack=") {
+                let values = values
+                    .parse()
+                    .map_err(|_| format!("Invalid stack limit '{values}'."))?;
+                options.max_stack = Some(values);
+            } else if let Some(backend) = arg.strip_prefix("--backend=") {
+                options.backend = backend.parse()?;
+            } else if arg == "--gc-stats" {
+                options.gc_stats = true;
+            } else if arg == "--disassemble" {
+                options.disassemble = true;
+            } else if arg == "--no-optimize" {
+                options.no_optimize = true;
+            } else if arg.starts_with("--") || options.script.is_some() {
+                return Err(format!("Unexpected argument '{arg}'."));
+            } else {
+                options.script = Some(arg);
+            }
+        }
+        Ok(options)
+    }
+    fn heap(&self) -> Heap {
+        self.max_heap.map_or_else(Heap::new, Heap::with_limit)
+    }
+    fn vm(&self, heap: &mut Heap) -> Vm {
+        Vm::new(
+            heap.allocator(),
+            self.gc_mode,
+            self.max_stack.unwrap_or(STACK_MAX),
+        )
+    }
+    /// Compiles `source` for the chosen backend.
+    fn compile(&self, source: &str, heap: &mut Heap, vm: &mut Vm) -> Result<Chunk, CompilerError> {
+        let parser = Parser::new(source, heap.allocator(), &mut vm.globals)
+            .with_optimizations(!self.no_optimize);
+        match self.backend {
+            Backend::Stack => parser
+                .collect::<Result<ChunkBuilder, _>>()
+                .and_then(Chunk::try_from),
+            Backend::Register => parser.collect::<Result<_, _>>().and_then(register::compile),
+        }
+    }
+    /// Runs a chunk compiled by [`Options::compile`].
+    fn run(&self, state: &mut RuntimeState) -> VmResult<()> {
+        match self.backend {
+            Backend::Stack => run_time::run(state),
+            Backend::Register => register::run(state),
         }
     }
 }
 
-fn run_repl() -> Result<(), Error> {
+fn report_gc_stats(heap: &Heap, options: &Options) {
+    if options.gc_stats {
+        eprintln!("gc mode: {}\n{}", options.gc_mode, heap.stats());
+    }
+}
+
+fn disassemble(chunk: &Chunk, options: &Options) {
+    match (options.disassemble, options.backend) {
+        (false, _) => (),
+        (true, Backend::Stack) => eprint!("{chunk}"),
+        (true, Backend::Register) => eprint!("{}", register::Listing(chunk)),
+    }
+}
+
+fn main_loop<'a>(
+    vm: &mut Vm,
+    call_frame: &mut CallFrame<'a>,
+    options: &Options,
+) -> Result<(), RuntimeError> {
+    options.run(&mut RuntimeState::new(vm, call_frame))
+}
+
+fn run_repl(options: &Options) -> Result<(), Error> {
     let mut buffer = String::new();
-    let mut heap = Heap::new();
-    let mut vm = Vm::new(heap.allocator());
+    let mut heap = options.heap();
+    let mut vm = options.vm(&mut heap);
     loop {
         buffer.clear();
         print!("> ");
         io::stdout().flush()?;
         if 0 == io::stdin().read_line(&mut buffer)? {
+            report_gc_stats(&heap, options);
             break Ok(());
         }
-        let chunk = match Parser::new(&buffer, heap.allocator())
-            .collect::<Result<Chunk, CompilerError>>()
-        {
+        let chunk = match options.compile(&buffer, &mut heap, &mut vm) {
             Ok(c) => c,
             Err(err) => {
                 eprintln!("{err}");
                 continue;
             }
         };
+        disassemble(&chunk, options);
         let mut frame = CallFrame::new(&chunk);
-        if let Err(err) = main_loop(&mut vm, &mut frame) {
+        if let Err(err) = main_loop(&mut vm, &mut frame, options) {
             eprintln!("{err}");
             continue;
         }
     }
 }
-fn run_file(file_name: &str) -> Result<(), Error> {
+fn run_file(file_name: &str, options: &Options) -> Result<(), Error> {
     let mut file = File::open(file_name)?;
     let mut file_contents = String::new();
     file.read_to_string(&mut file_contents)?;
 
-    let mut heap = Heap::new();
-    let mut vm = Vm::new(heap.allocator());
-    let chunk =
-        Parser::new(&file_contents, heap.allocator()).collect::<Result<Chunk, CompilerError>>()?;
+    let mut heap = options.heap();
+    let mut vm = options.vm(&mut heap);
+    let chunk = options.compile(&file_contents, &mut heap, &mut vm)?;
+    disassemble(&chunk, options);
     let mut frame = CallFrame::new(&chunk);
-    main_loop(&mut vm, &mut frame).map_err(|e| e.into())
+    let result = main_loop(&mut vm, &mut frame, options);
+    report_gc_stats(&heap, options);
+    result.map_err(|e| e.into())
+}
+fn usage_error(err: String) -> ! {
+    eprintln!("{err}\n{USAGE}");
+    exit(64);
 }
 fn main() {
-    let args = std::env::args().collect::<Vec<_>>();
-    if let Err(err) = if args.len() > 1 {
-        run_file(&args[1])
+    let mut args = std::env::args().skip(1).peekable();
+    let result = if args.next_if(|arg| arg == "bench").is_some() {
+        let bench = BenchOptions::parse(args).unwrap_or_else(|err| usage_error(err));
+        bench::run(&bench)
+    } else if args.next_if(|arg| arg == "build").is_some() {
+        let build = BuildOptions::parse(args).unwrap_or_else(|err| usage_error(err));
+        aot::run(&build)
     } else {
-        run_repl()
-    } {
+        let options = Options::parse(args).unwrap_or_else(|err| usage_error(err));
+        if let Some(script) = &options.script {
+            run_file(script, &options)
+        } else {
+            run_repl(&options)
+        }
+    };
+    if let Err(err) = result {
         eprintln!("{err}");
         exit(1);
     }
@@ -0,0 +1,307 @@
+//! This module translates the compiler's stack code into register code.
+//!
+//! The stack is simulated while translating, each slot holding the operand
+//! its value can be read from. Pushing a constant or a local emits nothing,
+//! so most of the stack machine's loads disappear into the operands of the
+//! instructions using them. A slot's value is only copied into its own
+//! register when something needs it there: a local being read, a register
+//! about to be overwritten, or a jump, across which every slot has to be
+//! in its register.
+use std::collections::BTreeMap;
+
+use super::{CONSTANT_OPERAND, R_ENTER, R_LOAD, R_LOAD_LONG};
+use crate::{
+    byte_code::{Chunk, LinesBuilder, Location, OpCode, CONSTANTS_MAX, *},
+    compiler::{CompilerError, CompilerResult},
+    value::Value,
+};
+
+/// Where an instruction reads a value from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Register(u16),
+    Constant(u16),
+}
+
+impl Operand {
+    fn encode(self) -> u16 {
+        match self {
+            Self::Register(register) => register,
+            Self::Constant(index) => index | CONSTANT_OPERAND,
+        }
+    }
+}
+
+struct CodeGen {
+    code: Vec<u8>,
+    values: Vec<Value>,
+    /// The index of every constant in `values`, so each is stored once.
+    value_indices: BTreeMap<Value, usize>,
+    lines: LinesBuilder,
+    /// The location of the instruction being translated.
+    location: Location,
+    /// The operand every slot of the simulated stack can be read from.
+    stack: Vec<Operand>,
+    /// The most slots the stack has held, which is how many registers
+    /// there are.
+    registers: usize,
+}
+
+impl CodeGen {
+    fn error(&self, message: &str) -> CompilerError {
+        CompilerError::at_line(message, self.location.line as usize)
+    }
+    fn register(&self, slot: usize) -> CompilerResult<u16> {
+        u16::try_from(slot)
+            .ok()
+            .filter(|slot| slot & CONSTANT_OPERAND == 0)
+            .ok_or_else(|| self.error("Too many registers in one chunk."))
+    }
+    fn emit(&mut self, op_code: u8, operands: &[u16]) {
+        self.lines.push(self.code.len(), self.location);
+        self.code.push(op_code);
+        for operand in operands {
+            self.code.extend_from_slice(&operand.to_be_bytes());
+        }
+    }
+    fn push(&mut self, operand: Operand) {
+        self.stack.push(operand);
+        self.registers = self.registers.max(self.stack.len());
+    }
+    fn pop(&mut self) -> Operand {
+        self.stack.pop().expect("Stack code popped an empty stack.")
+    }
+    fn top(&self) -> Operand {
+        *self
+            .stack
+            .last()
+            .expect("Stack code peeked an empty stack.")
+    }
+    /// Returns the register the next value pushed belongs in.
+    fn next_register(&self) -> CompilerResult<u16> {
+        self.register(self.stack.len())
+    }
+    /// Returns an operand reading `value`, loading it into the next
+    /// register if its index doesn't fit in an operand.
+    fn constant(&mut self, value: Value) -> CompilerResult<Operand> {
+        let index = *self.value_indices.entry(value).or_insert_with(|| {
+            self.values.push(value);
+            self.values.len() - 1
+        });
+        if let Ok(index @ 0..CONSTANT_OPERAND) = u16::try_from(index) {
+            return Ok(Operand::Constant(index));
+        }
+        if index >= CONSTANTS_MAX {
+            return Err(self.error("Too many constants in one chunk."));
+        }
+        let register = self.next_register()?;
+        self.registers = self.registers.max(self.stack.len() + 1);
+        let [_, index @ ..] = (index as u32).to_be_bytes();
+        self.lines.push(self.code.len(), self.location);
+        self.code.push(R_LOAD_LONG);
+        self.code.extend_from_slice(&register.to_be_bytes());
+        self.code.extend_from_slice(&index);
+        Ok(Operand::Register(register))
+    }
+    /// Copies the value of `slot` into its own register.
+    fn materialize(&mut self, slot: usize) -> CompilerResult<()> {
+        let register = self.register(slot)?;
+        if self.stack[slot] != Operand::Register(register) {
+            self.emit(R_LOAD, &[register, self.stack[slot].encode()]);
+            self.stack[slot] = Operand::Register(register);
+        }
+        Ok(())
+    }
+    fn materialize_all(&mut self) -> CompilerResult<()> {
+        for slot in 0..self.stack.len() {
+            self.materialize(slot)?;
+        }
+        Ok(())
+    }
+    /// Returns the register holding the local in `slot`.
+    fn local(&mut self, slot: u8) -> CompilerResult<Operand> {
+        self.materialize(slot as usize)?;
+        Ok(self.stack[slot as usize])
+    }
+    /// Stores the value on top of the stack in the local in `slot`, first
+    /// copying out any reads of the local still waiting on the stack.
+    fn set_local(&mut self, slot: u8) -> CompilerResult<()> {
+        let register = self.register(slot as usize)?;
+        for other in 0..self.stack.len() {
+            if other != slot as usize && self.stack[other] == Operand::Register(register) {
+                self.materialize(other)?;
+            }
+        }
+        let value = self.top();
+        if value != Operand::Register(register) {
+            self.emit(R_LOAD, &[register, value.encode()]);
+        }
+        self.stack[slot as usize] = Operand::Register(register);
+        Ok(())
+    }
+    /// Pops the operands of a binary instruction and pushes its result.
+    fn binary(&mut self, op_code: u8, b: Operand) -> CompilerResult<()> {
+        let a = self.pop();
+        let dst = self.next_register()?;
+        self.emit(op_code, &[dst, a.encode(), b.encode()]);
+        self.push(Operand::Register(dst));
+        Ok(())
+    }
+    fn unary(&mut self, op_code: u8) -> CompilerResult<()> {
+        let a = self.pop();
+        let dst = self.next_register()?;
+        self.emit(op_code, &[dst, a.encode()]);
+        self.push(Operand::Register(dst));
+        Ok(())
+    }
+}
+
+/// Translates the stack code of a whole script into register code,
+/// failing if it needs more registers or constants than operands can name.
+pub(crate) fn compile(code: Vec<(OpCode, Location)>) -> CompilerResult<Chunk> {
+    let mut gen = CodeGen {
+        code: Vec::new(),
+        values: Vec::new(),
+        value_indices: BTreeMap::new(),
+        lines: LinesBuilder::new(),
+        location: Location::default(),
+        stack: Vec::new(),
+        registers: 0,
+    };
+    gen.location = code
+        .first()
+        .map(|(_, location)| *location)
+        .unwrap_or_default();
+    // Filled in once the number of registers is known.
+    gen.emit(R_ENTER, &[0]);
+    // The stack depth at every jump target, the offset every instruction
+    // was translated to, and the jumps to patch with their targets and
+    // locations.
+    let mut targets = vec![None; code.len() + 1];
+    let mut offsets = vec![0; code.len() + 1];
+    let mut jumps = Vec::new();
+    let mut reachable = true;
+    for (i, &(op_code, location)) in code.iter().enumerate() {
+        gen.location = location;
+        if let Some(depth) = targets[i] {
+            if reachable {
+                gen.materialize_all()?;
+            }
+            gen.stack = (0..depth)
+                .map(|slot| Operand::Register(slot as u16))
+                .collect();
+            reachable = true;
+        }
+        offsets[i] = gen.code.len();
+        if !reachable {
+            continue;
+        }
+        match op_code {
+            OpCode::Constant(v) => {
+                let operand = gen.constant(v)?;
+                gen.push(operand);
+            }
+            OpCode::Nil | OpCode::True | OpCode::False => {
+                let value = match op_code {
+                    OpCode::Nil => Value::default(),
+                    _ => matches!(op_code, OpCode::True).into(),
+                };
+                let operand = gen.constant(value)?;
+                gen.push(operand);
+            }
+            OpCode::Add
+            | OpCode::Sub
+            | OpCode::Mul
+            | OpCode::Div
+            | OpCode::Equal
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::NotEqual
+            | OpCode::GreaterEqual
+            | OpCode::LessEqual => {
+                let b = gen.pop();
+                gen.binary(op_code.into(), b)?;
+            }
+            OpCode::AddConstant(v) | OpCode::SubConstant(v) => {
+                let b = gen.constant(v)?;
+                let op = if matches!(op_code, OpCode::AddConstant(_)) {
+                    OP_ADD
+                } else {
+                    OP_SUB
+                };
+                gen.binary(op, b)?;
+            }
+            OpCode::AddLocal(slot) | OpCode::SubLocal(slot) => {
+                let b = gen.local(slot)?;
+                let op = if matches!(op_code, OpCode::AddLocal(_)) {
+                    OP_ADD
+                } else {
+                    OP_SUB
+                };
+                gen.binary(op, b)?;
+            }
+            OpCode::Neg | OpCode::Not => gen.unary(op_code.into())?,
+            OpCode::GetLocal(slot) => {
+                let operand = gen.local(slot)?;
+                gen.push(operand);
+            }
+            OpCode::SetLocal(slot) => gen.set_local(slot)?,
+            OpCode::SetLocalPop(slot) => {
+                gen.set_local(slot)?;
+                gen.pop();
+            }
+            OpCode::GetGlobal(slot) => {
+                let dst = gen.next_register()?;
+                gen.emit(OP_GET_GLOBAL, &[dst, slot]);
+                gen.push(Operand::Register(dst));
+            }
+            OpCode::SetGlobal(slot) | OpCode::SetGlobalPop(slot) => {
+                let value = gen.top();
+                gen.emit(OP_SET_GLOBAL, &[slot, value.encode()]);
+                if matches!(op_code, OpCode::SetGlobalPop(_)) {
+                    gen.pop();
+                }
+            }
+            OpCode::DefineGlobal(slot) | OpCode::DefineConstGlobal(slot) => {
+                let value = gen.pop();
+                gen.emit(op_code.into(), &[slot, value.encode()]);
+            }
+            OpCode::Print => {
+                let value = gen.pop();
+                gen.emit(OP_PRINT, &[value.encode()]);
+            }
+            OpCode::Pop => {
+                gen.pop();
+            }
+            OpCode::Jump(offset) | OpCode::JumpIfFalse(offset) => {
+                gen.materialize_all()?;
+                targets[i + 1 + offset as usize] = Some(gen.stack.len());
+                // Offsets take two operands' worth of bytes, since register
+                // code is several times the size of its stack code.
+                if let OpCode::JumpIfFalse(_) = op_code {
+                    let condition = gen.top();
+                    gen.emit(OP_JUMP_IF_FALSE, &[condition.encode(), 0xffff, 0xffff]);
+                } else {
+                    gen.emit(OP_JUMP, &[0xffff, 0xffff]);
+                }
+                jumps.push((gen.code.len() - 4, i + 1 + offset as usize, location));
+            }
+            OpCode::NoMatch => {
+                let value = gen.top();
+                gen.emit(OP_NO_MATCH, &[value.encode()]);
+            }
+            OpCode::Return => gen.emit(OP_RETURN, &[]),
+        }
+        reachable = !matches!(op_code, OpCode::Jump(_));
+    }
+    for (pos, target, location) in jumps {
+        let Ok(offset) = u32::try_from(offsets[target] - pos - 4) else {
+            gen.location = location;
+            return Err(gen.error("Too much code to jump over."));
+        };
+        gen.code[pos..pos + 4].copy_from_slice(&offset.to_be_bytes());
+    }
+    let registers = gen.register(gen.registers)?;
+    gen.code[1..3].copy_from_slice(&registers.to_be_bytes());
+    Ok(Chunk::new(gen.code, gen.values, gen.lines.finalize()))
+}
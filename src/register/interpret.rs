@@ -0,0 +1,237 @@
+use std::fmt::Display;
+
+use super::{CONSTANT_OPERAND, R_ENTER, R_LOAD, R_LOAD_LONG};
+use crate::{
+    byte_code::{Chunk, *},
+    frame::pc::PositionCounter,
+    run_time::{
+        vm::{BinaryOp, UnaryOp, Vm, VmResult},
+        RuntimeState,
+    },
+    runtime_error,
+    value::Value,
+};
+
+/// Runs register code made by [`compile`] until it returns, sharing the
+/// stack machine's implementation of every instruction with a counterpart.
+///
+/// [`compile`]: super::compile
+pub(crate) fn run<'a, 'b>(state: &mut RuntimeState<'b, 'a>) -> VmResult<()> {
+    let chunk = state.get_frames().chunk;
+    let (code, constants) = (chunk.code(), chunk.constants());
+    let mut ip = *state.get_frames().position_conunter;
+    let mut executed = 0;
+    macro_rules! read_byte {
+        () => {{
+            ip += 1;
+            code[ip - 1]
+        }};
+    }
+    macro_rules! read_u16 {
+        () => {{
+            ip += 2;
+            u16::from_be_bytes([code[ip - 2], code[ip - 1]])
+        }};
+    }
+    macro_rules! read_u32 {
+        () => {{
+            ip += 4;
+            u32::from_be_bytes([code[ip - 4], code[ip - 3], code[ip - 2], code[ip - 1]])
+        }};
+    }
+    macro_rules! read_operand {
+        () => {{
+            let operand = read_u16!();
+            if operand & CONSTANT_OPERAND == 0 {
+                *state.get_vm().stack.get(operand as usize).unwrap()
+            } else {
+                constants[(operand & !CONSTANT_OPERAND) as usize]
+            }
+        }};
+    }
+    macro_rules! write_register {
+        ($register:expr, $value:expr) => {
+            state.get_vm().stack.set($register as usize, $value)
+        };
+    }
+    loop {
+        state.position = ip.into();
+        executed += 1;
+        match read_byte!() {
+            R_ENTER => {
+                let registers = read_u16!() as usize;
+                while state.get_vm().stack.len() < registers {
+                    if state.get_vm().stack.push(Value::default()).is_err() {
+                        return runtime_error!(state, "Stack overflow.");
+                    }
+                }
+            }
+            R_LOAD => {
+                let dst = read_u16!();
+                let v = read_operand!();
+                write_register!(dst, v);
+            }
+            R_LOAD_LONG => {
+                let dst = read_u16!();
+                let index = u32::from_be_bytes([0, read_byte!(), read_byte!(), read_byte!()]);
+                write_register!(dst, constants[index as usize]);
+            }
+            op_code @ (OP_ADD | OP_SUB | OP_MUL | OP_DIV | OP_EQUAL | OP_GREATER | OP_LESS
+            | OP_NOT_EQUAL | OP_GREATER_EQUAL | OP_LESS_EQUAL) => {
+                let dst = read_u16!();
+                let (a, b) = (read_operand!(), read_operand!());
+                let v = Vm::binary_instruction(state, BinaryOp::new(op_code, a, b))?;
+                write_register!(dst, v);
+            }
+            op_code @ (OP_NEG | OP_NOT) => {
+                let dst = read_u16!();
+                let a = read_operand!();
+                let v = Vm::unary_instruction(state, UnaryOp::new(op_code, a))?;
+                write_register!(dst, v);
+            }
+            op_code @ (OP_DEFINE_GLOBAL | OP_DEFINE_CONST_GLOBAL) => {
+                let slot = read_u16!();
+                let v = read_operand!();
+                let global = *state.get_vm().globals.get(slot);
+                if global.constant {
+                    return runtime_error!(state, "Cannot redefine constant '{}'.", global.name);
+                }
+                let global = state.get_vm().globals.get_mut(slot);
+                global.value = Some(v);
+                global.constant = op_code == OP_DEFINE_CONST_GLOBAL;
+            }
+            OP_GET_GLOBAL => {
+                let dst = read_u16!();
+                let global = *state.get_vm().globals.get(read_u16!());
+                let Some(value) = global.value else {
+                    return runtime_error!(state, "Undefined variable {}.", global.name);
+                };
+                write_register!(dst, value);
+            }
+            OP_SET_GLOBAL => {
+                let slot = read_u16!();
+                let v = read_operand!();
+                let global = *state.get_vm().globals.get(slot);
+                if global.constant {
+                    return runtime_error!(state, "Cannot assign to constant '{}'.", global.name);
+                }
+                if global.value.is_none() {
+                    return runtime_error!(state, "Undefined variable {}.", global.name);
+                }
+                state.get_vm().globals.get_mut(slot).value = Some(v);
+            }
+            OP_JUMP => {
+                let offset = read_u32!();
+                ip += offset as usize;
+            }
+            OP_JUMP_IF_FALSE => {
+                let condition = read_operand!();
+                let offset = read_u32!();
+                if condition.is_falsey() {
+                    ip += offset as usize;
+                }
+            }
+            OP_NO_MATCH => {
+                let v = read_operand!();
+                return runtime_error!(state, "No match arm for value {}.", v);
+            }
+            OP_PRINT => {
+                println!("{}", read_operand!());
+            }
+            OP_RETURN => {
+                state.get_vm().stack.reset();
+                state.get_frames().position_conunter = ip.into();
+                state.instructions += executed;
+                return Ok(());
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Lists register code with the offsets and lines of its instructions.
+pub(crate) struct Listing<'a>(pub(crate) &'a Chunk);
+
+impl Listing<'_> {
+    /// Names the operand encoded as `operand`.
+    fn operand(&self, operand: u16) -> String {
+        if operand & CONSTANT_OPERAND == 0 {
+            format!("r{operand}")
+        } else {
+            self.0.constants()[(operand & !CONSTANT_OPERAND) as usize].to_string()
+        }
+    }
+}
+
+impl Display for Listing<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let code = self.0.code();
+        let mut pos = 0;
+        let mut line = None;
+        while pos < code.len() {
+            let u16_at = |n: usize| u16::from_be_bytes([code[pos + n], code[pos + n + 1]]);
+            let u32_at = |n: usize| u32::from_be_bytes([0, 1, 2, 3].map(|i| code[pos + n + i]));
+            let (text, size) = match code[pos] {
+                R_ENTER => (format!("ENTER {}", u16_at(1)), 3),
+                R_LOAD => (
+                    format!("LOAD r{} {}", u16_at(1), self.operand(u16_at(3))),
+                    5,
+                ),
+                R_LOAD_LONG => {
+                    let index =
+                        u32::from_be_bytes([0, code[pos + 3], code[pos + 4], code[pos + 5]]);
+                    let value = self.0.constants()[index as usize];
+                    (format!("LOAD r{} {value}", u16_at(1)), 6)
+                }
+                op_code @ (OP_ADD | OP_SUB | OP_MUL | OP_DIV | OP_EQUAL | OP_GREATER | OP_LESS
+                | OP_NOT_EQUAL | OP_GREATER_EQUAL | OP_LESS_EQUAL) => {
+                    let (a, b) = (self.operand(u16_at(3)), self.operand(u16_at(5)));
+                    let name = OpCode::from(op_code);
+                    (format!("{name} r{} {a} {b}", u16_at(1)), 7)
+                }
+                op_code @ (OP_NEG | OP_NOT) => {
+                    let name = OpCode::from(op_code);
+                    (
+                        format!("{name} r{} {}", u16_at(1), self.operand(u16_at(3))),
+                        5,
+                    )
+                }
+                op_code @ (OP_DEFINE_GLOBAL | OP_DEFINE_CONST_GLOBAL | OP_SET_GLOBAL) => {
+                    let name = match op_code {
+                        OP_DEFINE_GLOBAL => "DEFINE_GLOBAL",
+                        OP_DEFINE_CONST_GLOBAL => "DEFINE_CONST_GLOBAL",
+                        _ => "SET_GLOBAL",
+                    };
+                    (
+                        format!("{name} {} {}", u16_at(1), self.operand(u16_at(3))),
+                        5,
+                    )
+                }
+                OP_GET_GLOBAL => (format!("GET_GLOBAL r{} {}", u16_at(1), u16_at(3)), 5),
+                OP_JUMP => (format!("JUMP +{}", u32_at(1)), 5),
+                OP_JUMP_IF_FALSE => {
+                    let condition = self.operand(u16_at(1));
+                    (format!("JUMP_IF_FALSE {condition} +{}", u32_at(3)), 7)
+                }
+                OP_NO_MATCH => (format!("NO_MATCH {}", self.operand(u16_at(1))), 3),
+                OP_PRINT => (format!("PRINT {}", self.operand(u16_at(1))), 3),
+                OP_RETURN => ("RETURN".to_string(), 1),
+                _ => unreachable!(),
+            };
+            let location = self
+                .0
+                .get_location(PositionCounter::from(pos))
+                .unwrap_or_default();
+            write!(f, "{pos:04} ")?;
+            if line == Some(location.line) {
+                write!(f, "   | ")?;
+            } else {
+                write!(f, "{:4} ", location.line)?;
+            }
+            writeln!(f, "{text}")?;
+            line = Some(location.line);
+            pos += size;
+        }
+        Ok(())
+    }
+}
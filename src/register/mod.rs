@@ -0,0 +1,118 @@
+//! A register machine the compiler's stack code can be translated to, so
+//! the two can be compared. Its registers are the slots of the vm's stack,
+//! register `n` standing for the value the stack machine would keep `n`
+//! slots from the bottom, so the heap, the collector's roots and runtime
+//! errors work the same for both.
+//!
+//! Instructions are three-address: they name the register they write and
+//! the operands they read, each either a register or a constant. Binary
+//! and unary instructions, and those that have a counterpart on the stack
+//! machine, are encoded with the stack machine's op codes so the vm's
+//! implementations are shared. Jump offsets are four bytes rather than
+//! two, as register code is several times the size of its stack code.
+use std::str::FromStr;
+
+pub(crate) mod codegen;
+pub(crate) mod interpret;
+pub(crate) use codegen::compile;
+pub(crate) use interpret::{run, Listing};
+
+/// Set in an operand that's a constant index instead of a register.
+pub(crate) const CONSTANT_OPERAND: u16 = 0x8000;
+
+/// Grows the stack to hold every register: `ENTER count`.
+pub(crate) const R_ENTER: u8 = 64;
+/// Copies an operand into a register: `LOAD dst src`.
+pub(crate) const R_LOAD: u8 = 65;
+/// Loads a constant whose index doesn't fit in an operand, with a three
+/// byte index: `LOAD_LONG dst index`.
+pub(crate) const R_LOAD_LONG: u8 = 66;
+
+/// Which machine scripts are compiled for and run on.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Backend {
+    #[default]
+    Stack,
+    Register,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stack" => Ok(Self::Stack),
+            "register" => Ok(Self::Register),
+            _ => Err(format!("Unknown backend '{s}'.")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        byte_code::{Chunk, ChunkBuilder, Location, OpCode},
+        frame::CallFrame,
+        run_time::{self, RuntimeState},
+        test_vm::TestVm,
+    };
+
+    /// Runs `source` on the stack machine or, translated, on the register
+    /// machine, returning the globals it leaves and the instructions run.
+    fn run(source: &str, register: bool) -> (Vec<String>, u64) {
+        let mut vm = TestVm::new();
+        let code = vm.compile(source, true).unwrap();
+        let chunk = match register {
+            true => super::compile(code).unwrap(),
+            false => Chunk::try_from(code.into_iter().collect::<ChunkBuilder>()).unwrap(),
+        };
+        let mut frame = CallFrame::new(&chunk);
+        let mut state = RuntimeState::new(&mut vm.vm, &mut frame);
+        match register {
+            true => super::run(&mut state).unwrap(),
+            false => run_time::run(&mut state).unwrap(),
+        }
+        let instructions = state.instructions();
+        let globals = vm
+            .vm
+            .globals
+            .iter()
+            .map(|g| format!("{:?}", g.value.map(|v| v.to_string())));
+        (globals.collect(), instructions)
+    }
+
+    #[test]
+    fn agrees_with_the_stack_machine() {
+        let source = "
+            var g = 2; const s = \"s\";
+            var t = s + \"t\";
+            {
+                var a = 1; var b = a;
+                a = 5;
+                b = a = a + b * g;
+                g = match (a) { 1 => 1, x if x > 5 => x - b + 100, _ => -1 };
+                var c = b;
+                b = 3;
+                t = t + match (c == 6) { true => \"yes\", _ => \"no\" };
+                g = g + c - b;
+            }
+        ";
+        let (stack, stack_instructions) = run(source, false);
+        let (register, register_instructions) = run(source, true);
+        assert_eq!(stack, register);
+        assert!(register_instructions < stack_instructions);
+    }
+    #[test]
+    fn jumps_over_long_arms() {
+        let terms = vec!["g"; 6000].join(" + ");
+        let source = format!("var g = 1; g = match (g) {{ 1 => {terms}, _ => 0 }};");
+        let (stack, _) = run(&source, false);
+        assert_eq!(stack, run(&source, true).0);
+        assert_eq!(vec!["Some(\"6000\")"], stack);
+    }
+    #[test]
+    fn too_many_registers() {
+        let code = vec![(OpCode::Nil, Location::default()); 1 << 15];
+        let err = super::compile(code).unwrap_err().to_string();
+        assert!(err.contains("Too many registers in one chunk."), "{err}");
+    }
+}
@@ -1,16 +1,17 @@
 use std::fmt::Display;
 
 use super::{vm::VmResult, RuntimeState};
+use crate::byte_code::Location;
 
 #[derive(Clone, Default, Debug)]
 pub struct RuntimeError {
     message: String,
-    line: u8,
+    location: Location,
 }
 #[macro_export]
 macro_rules! runtime_error {
     ($runtime:expr, $($args:tt)*) => {
-	crate::run_time::error::runtime_error($runtime, std::format_args!($($args)*))
+	$crate::run_time::error::runtime_error($runtime, std::format_args!($($args)*))
     }
 }
 
@@ -18,8 +19,8 @@ impl Display for RuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Error: {}\n[line {}] in script\n",
-            self.message, self.line
+            "Error: {}\n[{}] in script\n",
+            self.message, self.location
         )
     }
 }
@@ -30,10 +31,10 @@ pub(crate) fn runtime_error<'a, 'b, T>(
     message: impl ToString,
 ) -> VmResult<T> {
     let pos = state.get_position();
-    let line = state.get_frames().chunk.get_line(pos).unwrap();
+    let location = state.get_frames().chunk.get_location(pos).unwrap();
     state.get_vm().stack.reset();
     Err(RuntimeError {
         message: message.to_string(),
-        line,
+        location,
     })
 }
@@ -1,81 +1,156 @@
-use std::ops::ControlFlow;
-
 use super::vm::{BinaryOp, UnaryOp, Vm, VmResult};
 use super::RuntimeState;
-use crate::byte_code::OpCode;
+use crate::byte_code::*;
 use crate::runtime_error;
+use crate::stack::StackOverflow;
 use crate::value::Value;
 
-pub(crate) fn interpret_instruction<'a, 'b>(
-    state: &mut RuntimeState<'b, 'a>,
-    op_code: OpCode,
-) -> ControlFlow<VmResult<()>> {
-    match op_code {
-        OpCode::Constant(v) => state.get_vm().push(v),
-        OpCode::Add
-        | OpCode::Sub
-        | OpCode::Mul
-        | OpCode::Div
-        | OpCode::Equal
-        | OpCode::Greater
-        | OpCode::Less => {
-            let (a, b) = (
-                *state.get_vm().stack.peek(1).unwrap(),
-                *state.get_vm().stack.peek(0).unwrap(),
-            );
-            let v = match Vm::binary_instruction(state, BinaryOp::new(op_code, a, b)) {
-                Ok(v) => {
-                    (0..=1).for_each(|_| {
-                        _ = state.get_vm().stack.pop();
-                    });
-                    v
-                }
-                Err(e) => return ControlFlow::Break(Err(e)),
-            };
+/// Pushes `value` onto the vm's stack, failing if it's full.
+#[inline(always)]
+fn push<'a, 'b>(state: &mut RuntimeState<'b, 'a>, value: Value) -> VmResult<()> {
+    match state.get_vm().stack.push(value) {
+        Ok(()) => Ok(()),
+        Err(StackOverflow) => runtime_error!(state, "Stack overflow."),
+    }
+}
 
-            state.get_vm().push(v);
-        }
-        OpCode::Neg | OpCode::Not => {
-            let v = *state.get_vm().stack.peek(0).unwrap();
-            let v = match Vm::unary_instruction(state, UnaryOp::new(op_code, v)) {
-                Ok(v) => {
-                    _ = state.get_vm().stack.pop();
-                    v
+/// Runs the frame's chunk from its position until it returns. Instructions
+/// are dispatched on their first byte and their operands read in place,
+/// the position is only written back to the state for error reporting.
+pub(crate) fn run<'a, 'b>(state: &mut RuntimeState<'b, 'a>) -> VmResult<()> {
+    let chunk = state.get_frames().chunk;
+    let (code, constants) = (chunk.code(), chunk.constants());
+    let mut ip = *state.get_frames().position_conunter;
+    let mut executed = 0;
+    macro_rules! read_byte {
+        () => {{
+            ip += 1;
+            code[ip - 1]
+        }};
+    }
+    macro_rules! read_u16 {
+        () => {{
+            ip += 2;
+            u16::from_be_bytes([code[ip - 2], code[ip - 1]])
+        }};
+    }
+    loop {
+        state.position = ip.into();
+        executed += 1;
+        match read_byte!() {
+            OP_CONSTANT => {
+                let v = constants[read_byte!() as usize];
+                push(state, v)?;
+            }
+            OP_CONSTANT_LONG => {
+                let index = u32::from_be_bytes([0, read_byte!(), read_byte!(), read_byte!()]);
+                push(state, constants[index as usize])?;
+            }
+            op_code @ (OP_ADD | OP_SUB | OP_MUL | OP_DIV | OP_EQUAL | OP_GREATER | OP_LESS
+            | OP_NOT_EQUAL | OP_GREATER_EQUAL | OP_LESS_EQUAL) => {
+                // The operands stay on the stack, where the collector sees
+                // them, until the result has been computed.
+                let (a, b) = (
+                    *state.get_vm().stack.peek(1).unwrap(),
+                    *state.get_vm().stack.peek(0).unwrap(),
+                );
+                let v = Vm::binary_instruction(state, BinaryOp::new(op_code, a, b))?;
+                state.get_vm().pop();
+                state.get_vm().pop();
+                push(state, v)?;
+            }
+            op_code @ (OP_ADD_CONSTANT | OP_SUB_CONSTANT | OP_ADD_LOCAL | OP_SUB_LOCAL) => {
+                let b = match op_code {
+                    OP_ADD_CONSTANT | OP_SUB_CONSTANT => constants[read_byte!() as usize],
+                    _ => *state.get_vm().stack.get(read_byte!() as usize).unwrap(),
+                };
+                let a = *state.get_vm().stack.peek(0).unwrap();
+                let op_code = match op_code {
+                    OP_ADD_CONSTANT | OP_ADD_LOCAL => OP_ADD,
+                    _ => OP_SUB,
+                };
+                let v = Vm::binary_instruction(state, BinaryOp::new(op_code, a, b))?;
+                state.get_vm().pop();
+                push(state, v)?;
+            }
+            op_code @ (OP_NEG | OP_NOT) => {
+                let v = state.get_vm().pop().unwrap();
+                let v = Vm::unary_instruction(state, UnaryOp::new(op_code, v))?;
+                push(state, v)?;
+            }
+            OP_NIL => push(state, Value::default())?,
+            OP_TRUE => push(state, true.into())?,
+            OP_FALSE => push(state, false.into())?,
+            op_code @ (OP_DEFINE_GLOBAL | OP_DEFINE_CONST_GLOBAL) => {
+                let slot = read_u16!();
+                let global = *state.get_vm().globals.get(slot);
+                if global.constant {
+                    return runtime_error!(state, "Cannot redefine constant '{}'.", global.name);
                 }
-                Err(err) => return ControlFlow::Break(Err(err)),
-            };
-            state.get_vm().push(v);
-        }
-        OpCode::Nil => state.get_vm().push(Value::Nil),
-        OpCode::True => state.get_vm().push(true.into()),
-        OpCode::False => state.get_vm().push(false.into()),
-        OpCode::DefineGlobal(name) => {
-            let v = *state.get_vm().stack.peek(0).unwrap();
-            state.get_vm().globals.insert(name, v);
-            state.get_vm().pop();
-        }
-        OpCode::GetGlobal(name) => {
-            let Some(value) = state.get_vm().globals.get(&name) else {
-		return ControlFlow::Break(runtime_error!(state, "Undefined variable {}.", name));	    };
-            let value = *value;
-            state.get_vm().stack.push(value);
-        }
-        OpCode::SetGlobal(name) => {
-            let v = state.get_vm().stack.peek(0).map(|t| *t).unwrap();
-            if state.get_vm().globals.insert(name, v).is_none() {
-                state.get_vm().globals.remove(&name);
-                return ControlFlow::Break(runtime_error!(state, "Undefined variable {}.", name));
+                let v = state.get_vm().pop().unwrap();
+                let global = state.get_vm().globals.get_mut(slot);
+                global.value = Some(v);
+                global.constant = op_code == OP_DEFINE_CONST_GLOBAL;
             }
-        }
-        OpCode::Print => {
-            println!("{}", state.get_vm().stack.peek(0).unwrap());
-        }
-        OpCode::Pop => {
-            state.get_vm().pop();
-        }
-        OpCode::Return => {
-            return ControlFlow::Break(Ok(()));
+            OP_GET_GLOBAL => {
+                let global = *state.get_vm().globals.get(read_u16!());
+                let Some(value) = global.value else {
+                    return runtime_error!(state, "Undefined variable {}.", global.name);
+                };
+                push(state, value)?;
+            }
+            op_code @ (OP_SET_GLOBAL | OP_SET_GLOBAL_POP) => {
+                let slot = read_u16!();
+                let global = *state.get_vm().globals.get(slot);
+                if global.constant {
+                    return runtime_error!(state, "Cannot assign to constant '{}'.", global.name);
+                }
+                if global.value.is_none() {
+                    return runtime_error!(state, "Undefined variable {}.", global.name);
+                }
+                let v = *state.get_vm().stack.peek(0).unwrap();
+                state.get_vm().globals.get_mut(slot).value = Some(v);
+                if op_code == OP_SET_GLOBAL_POP {
+                    state.get_vm().pop();
+                }
+            }
+            OP_GET_LOCAL => {
+                let v = *state.get_vm().stack.get(read_byte!() as usize).unwrap();
+                push(state, v)?;
+            }
+            op_code @ (OP_SET_LOCAL | OP_SET_LOCAL_POP) => {
+                let v = *state.get_vm().stack.peek(0).unwrap();
+                state.get_vm().stack.set(read_byte!() as usize, v);
+                if op_code == OP_SET_LOCAL_POP {
+                    state.get_vm().pop();
+                }
+            }
+            OP_JUMP => {
+                let offset = read_u16!();
+                ip += offset as usize;
+            }
+            OP_JUMP_IF_FALSE => {
+                let offset = read_u16!();
+                if state.get_vm().stack.peek(0).unwrap().is_falsey() {
+                    ip += offset as usize;
+                }
+            }
+            OP_NO_MATCH => {
+                let v = *state.get_vm().stack.peek(0).unwrap();
+                return runtime_error!(state, "No match arm for value {}.", v);
+            }
+            OP_PRINT => {
+                println!("{}", state.get_vm().pop().unwrap());
+            }
+            OP_POP => {
+                state.get_vm().pop();
+            }
+            OP_RETURN => {
+                state.get_frames().position_conunter = ip.into();
+                state.instructions += executed;
+                return Ok(());
+            }
+            _ => unreachable!(),
         }
     }
-    ControlFlow::Continue(())
 }
@@ -1,17 +1,16 @@
 pub mod error;
 pub(crate) mod interpret;
 pub(crate) mod vm;
-use crate::{
-    byte_code::OpCode,
-    frame::{pc::PositionCounter, CallFrame},
-};
+use crate::frame::{pc::PositionCounter, CallFrame};
 pub use error::*;
 pub(crate) use interpret::*;
 use vm::Vm;
 pub(crate) struct RuntimeState<'a, 'b> {
-    position: PositionCounter,
+    pub(crate) position: PositionCounter,
     vm: &'a mut Vm,
     frames: &'a mut CallFrame<'b>,
+    /// The number of instructions executed up to the last return.
+    pub(crate) instructions: u64,
 }
 
 impl<'a, 'b> RuntimeState<'a, 'b> {
@@ -20,8 +19,12 @@ impl<'a, 'b> RuntimeState<'a, 'b> {
             position: Default::default(),
             vm,
             frames,
+            instructions: 0,
         }
     }
+    pub(crate) fn instructions(&self) -> u64 {
+        self.instructions
+    }
     #[inline(always)]
     pub(crate) fn get_position(&mut self) -> PositionCounter {
         self.position
@@ -34,8 +37,17 @@ impl<'a, 'b> RuntimeState<'a, 'b> {
     pub(crate) fn get_frames(&mut self) -> &mut CallFrame<'b> {
         self.frames
     }
-    pub(crate) fn advance_position(&mut self) -> OpCode {
-        self.position = self.frames.position_conunter;
-        self.frames.advance_position()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{heap::Heap, test_vm::TestVm};
+
+    #[test]
+    fn interned_strings_fit_a_full_heap() {
+        let mut vm = TestVm::with_heap(Heap::with_limit(0));
+        vm.run("var a = \"ab\"; var b = \"a\" + \"b\";").unwrap();
+        let err = vm.run("var c = \"a\" + \"c\";").unwrap_err();
+        assert!(err.contains("Out of memory."), "{err}");
     }
 }
@@ -1,8 +1,7 @@
-use std::collections::HashMap;
-
 use crate::{
-    byte_code::OpCode,
-    heap::{Allocator, ObjPtr, ObjString, Object},
+    byte_code::*,
+    globals::Globals,
+    heap::{Allocator, GcMode, Heap, HeapObject, ObjRope, ObjString, Object},
     run_time::{RuntimeError, RuntimeState},
     runtime_error,
     stack::Stack,
@@ -17,18 +16,24 @@ pub(crate) enum BinaryOp {
     Equal(Value, Value),
     Greater(Value, Value),
     Less(Value, Value),
+    NotEqual(Value, Value),
+    GreaterEqual(Value, Value),
+    LessEqual(Value, Value),
 }
 
 impl BinaryOp {
-    pub(crate) fn new(op_code: OpCode, a: Value, b: Value) -> Self {
+    pub(crate) fn new(op_code: u8, a: Value, b: Value) -> Self {
         match op_code {
-            OpCode::Add => Self::Add(a, b),
-            OpCode::Sub => Self::Sub(a, b),
-            OpCode::Mul => Self::Mul(a, b),
-            OpCode::Div => Self::Div(a, b),
-            OpCode::Equal => Self::Equal(a, b),
-            OpCode::Greater => Self::Greater(a, b),
-            OpCode::Less => Self::Less(a, b),
+            OP_ADD => Self::Add(a, b),
+            OP_SUB => Self::Sub(a, b),
+            OP_MUL => Self::Mul(a, b),
+            OP_DIV => Self::Div(a, b),
+            OP_EQUAL => Self::Equal(a, b),
+            OP_GREATER => Self::Greater(a, b),
+            OP_LESS => Self::Less(a, b),
+            OP_NOT_EQUAL => Self::NotEqual(a, b),
+            OP_GREATER_EQUAL => Self::GreaterEqual(a, b),
+            OP_LESS_EQUAL => Self::LessEqual(a, b),
             _ => unreachable!(),
         }
     }
@@ -39,10 +44,10 @@ pub(crate) enum UnaryOp {
     Not(Value),
 }
 impl UnaryOp {
-    pub(crate) fn new(op: OpCode, a: Value) -> Self {
+    pub(crate) fn new(op: u8, a: Value) -> Self {
         match op {
-            OpCode::Neg => Self::Negate(a),
-            OpCode::Not => Self::Not(a),
+            OP_NEG => Self::Negate(a),
+            OP_NOT => Self::Not(a),
             _ => unreachable!(),
         }
     }
@@ -50,25 +55,28 @@ impl UnaryOp {
 
 pub type VmResult<T> = std::result::Result<T, RuntimeError>;
 
+/// The shortest concatenation made into a rope. Shorter ones are copied
+/// into a new string, which costs less than a rope once it's printed or
+/// compared.
+const ROPE_MIN_LEN: usize = 64;
+
 pub(crate) struct Vm {
     pub(crate) stack: Stack<Value>,
-    pub(crate) globals: HashMap<ObjPtr<ObjString>, Value>,
+    pub(crate) globals: Globals,
     pub(crate) allocator: Allocator,
+    pub(crate) gc_mode: GcMode,
 }
 
 impl Vm {
-    pub(crate) fn new(allocator: Allocator) -> Self {
+    pub(crate) fn new(allocator: Allocator, gc_mode: GcMode, stack_limit: usize) -> Self {
         Self {
-            stack: Stack::new(),
-            globals: HashMap::new(),
+            stack: Stack::new(stack_limit),
+            globals: Globals::new(),
             allocator,
+            gc_mode,
         }
     }
     #[inline(always)]
-    pub(crate) fn push(&mut self, value: Value) {
-        self.stack.push(value);
-    }
-    #[inline(always)]
     pub(crate) fn pop(&mut self) -> Option<Value> {
         self.stack.pop()
     }
@@ -76,42 +84,108 @@ impl Vm {
         state: &mut RuntimeState<'a, 'b>,
         instruction: BinaryOp,
     ) -> VmResult<Value> {
-        let num = match instruction {
-            BinaryOp::Add(Value::Number(a), Value::Number(b)) => a + b,
-            BinaryOp::Add(Value::Object(a), Value::Object(b))
-                if a.is_obj::<ObjString>() && b.is_obj::<ObjString>() =>
-            {
-                return Ok(Vm::concatenate(state, a, b))
-            }
-            BinaryOp::Add(_, _) => {
-                return runtime_error!(state, "Operands must be two numbers or two strings")
+        let (a, b) = match instruction {
+            BinaryOp::Equal(a, b) => return Ok(Value::from(Vm::values_equal(state, a, b)?)),
+            BinaryOp::NotEqual(a, b) => return Ok(Value::from(!Vm::values_equal(state, a, b)?)),
+            BinaryOp::Add(a, b) if a.is_object() && b.is_object() => {
+                return match (a.try_into_object(), b.try_into_object()) {
+                    (Ok(a), Ok(b)) if a.is_string() && b.is_string() => {
+                        Vm::concatenate(state, a, b)
+                    }
+                    _ => runtime_error!(state, "Operands must be two numbers or two strings"),
+                };
             }
-            BinaryOp::Sub(Value::Number(a), Value::Number(b)) => a - b,
-            BinaryOp::Mul(Value::Number(a), Value::Number(b)) => a * b,
-            BinaryOp::Div(Value::Number(a), Value::Number(b)) => a / b,
-
-            BinaryOp::Less(Value::Number(a), Value::Number(b)) => return Ok(Value::Bool(a < b)),
-            BinaryOp::Greater(Value::Number(a), Value::Number(b)) => return Ok(Value::Bool(a > b)),
-            BinaryOp::Equal(a, b) => return Ok(Value::Bool(a == b)),
-            _ => return runtime_error!(state, "Operands must be two numbers."),
+            BinaryOp::Add(a, b)
+            | BinaryOp::Sub(a, b)
+            | BinaryOp::Mul(a, b)
+            | BinaryOp::Div(a, b)
+            | BinaryOp::Greater(a, b)
+            | BinaryOp::Less(a, b)
+            | BinaryOp::GreaterEqual(a, b)
+            | BinaryOp::LessEqual(a, b) => (a, b),
+        };
+        let (Ok(a), Ok(b)) = (a.try_into_number(), b.try_into_number()) else {
+            return match instruction {
+                BinaryOp::Add(..) => {
+                    runtime_error!(state, "Operands must be two numbers or two strings")
+                }
+                _ => runtime_error!(state, "Operands must be two numbers."),
+            };
         };
-        Ok(Value::Number(num))
+        let num = match instruction {
+            BinaryOp::Add(..) => a.checked_add(b),
+            BinaryOp::Sub(..) => a.checked_sub(b),
+            BinaryOp::Mul(..) => a.checked_mul(b),
+            BinaryOp::Div(..) if b == 0 => return runtime_error!(state, "Division by zero."),
+            BinaryOp::Div(..) => a.checked_div(b),
+            BinaryOp::Greater(..) => return Ok(Value::from(a > b)),
+            BinaryOp::Less(..) => return Ok(Value::from(a < b)),
+            BinaryOp::GreaterEqual(..) => return Ok(Value::from(a >= b)),
+            BinaryOp::LessEqual(..) => return Ok(Value::from(a <= b)),
+            BinaryOp::Equal(..) | BinaryOp::NotEqual(..) => unreachable!(),
+        };
+        match num {
+            Some(num) => Ok(num.into()),
+            None => runtime_error!(state, "Integer overflow."),
+        }
     }
     pub(crate) fn unary_instruction<'a, 'b>(
         state: &mut RuntimeState<'a, 'b>,
         instruction: UnaryOp,
     ) -> VmResult<Value> {
         Ok(match instruction {
-            UnaryOp::Negate(Value::Number(a)) => Value::Number(-a),
-            UnaryOp::Negate(_) => return runtime_error!(state, "Operand must be a number"),
+            UnaryOp::Negate(a) => match a.try_into_number() {
+                Ok(a) => match a.checked_neg() {
+                    Some(a) => a.into(),
+                    None => return runtime_error!(state, "Integer overflow."),
+                },
+                Err(_) => return runtime_error!(state, "Operand must be a number"),
+            },
             UnaryOp::Not(v) => !v,
         })
     }
+    /// Compares two values, which must be rooted in case garbage is
+    /// collected. Strings are interned so they're equal only if they're the
+    /// same object. A rope is interned the first time it's compared to a
+    /// string of its length, and keeps the interned string for next time.
+    fn values_equal<'a, 'b>(
+        state: &mut RuntimeState<'a, 'b>,
+        a: Value,
+        b: Value,
+    ) -> VmResult<bool> {
+        match (a.try_into_object(), b.try_into_object()) {
+            (Ok(a), Ok(b)) if a.is_obj::<ObjRope>() || b.is_obj::<ObjRope>() => {
+                if !a.is_string() || !b.is_string() || a.string_len() != b.string_len() {
+                    return Ok(false);
+                }
+                Ok(Vm::intern(state, a)? == Vm::intern(state, b)?)
+            }
+            _ => Ok(a == b),
+        }
+    }
+    /// Returns the interned string equal to the string or rope `obj`, which
+    /// must be rooted in case garbage is collected first.
+    fn intern<'a, 'b>(state: &mut RuntimeState<'a, 'b>, obj: Object) -> VmResult<Object> {
+        if !obj.is_obj::<ObjRope>() {
+            return Ok(obj);
+        }
+        let rope = obj.as_obj::<ObjRope>();
+        if let Some(string) = rope.as_ref().interned() {
+            return Ok(string);
+        }
+        let string = Vm::allocate_string(state, rope.as_ref().flatten())?;
+        state.vm.allocator.set_interned(obj, string);
+        Ok(string)
+    }
     pub(crate) fn concatenate<'a, 'b>(
         state: &mut RuntimeState<'a, 'b>,
         a: Object,
         b: Object,
-    ) -> Value {
+    ) -> VmResult<Value> {
+        if a.string_len() + b.string_len() - 2 >= ROPE_MIN_LEN {
+            return Ok(Vm::allocate_rope(state, a, b)?.into());
+        }
+        // Ropes are never this short, so both are strings.
         let (a, b) = (a.as_obj::<ObjString>(), b.as_obj::<ObjString>());
         let (a, b) = {
             let a = a.as_ref();
@@ -121,7 +195,66 @@ impl Vm {
             (&a[..a_len], &b[1..])
         };
         let result = format!("{a}{b}");
-        let obj = state.vm.allocator.allocate_string(result);
-        obj.into()
+        let obj = Vm::allocate_string(state, result)?;
+        Ok(obj.into())
+    }
+    /// Allocates a string, collecting garbage first if need be. A string
+    /// equal to one already interned takes no room, so it is returned
+    /// without reserving any.
+    pub(crate) fn allocate_string<'a, 'b>(
+        state: &mut RuntimeState<'a, 'b>,
+        string: String,
+    ) -> VmResult<Object> {
+        if let Some(obj) = state.vm.allocator.find_string(&string) {
+            return Ok(obj);
+        }
+        Vm::reserve(state, HeapObject::string_size(string.capacity()))?;
+        Ok(state.vm.allocator.allocate_string(string))
+    }
+    /// Allocates a rope joining `left` and `right`, which must be rooted
+    /// in case garbage is collected first.
+    fn allocate_rope<'a, 'b>(
+        state: &mut RuntimeState<'a, 'b>,
+        left: Object,
+        right: Object,
+    ) -> VmResult<Object> {
+        Vm::reserve(state, HeapObject::rope_size())?;
+        Ok(state.vm.allocator.allocate_obj(ObjRope::new(left, right)))
+    }
+    /// Makes room for `size` more bytes, collecting garbage first if the
+    /// heap has grown past its threshold or they wouldn't fit under its
+    /// limit.
+    fn reserve<'a, 'b>(state: &mut RuntimeState<'a, 'b>, size: usize) -> VmResult<()> {
+        let allocator = &state.vm.allocator;
+        if allocator.should_collect() || !allocator.fits(size) {
+            Vm::collect_garbage(state, false);
+        } else if state.vm.gc_mode == GcMode::Generational && allocator.should_collect_young() {
+            Vm::collect_garbage(state, true);
+        }
+        if !state.vm.allocator.fits(size) {
+            return runtime_error!(state, "Out of memory.");
+        }
+        Ok(())
+    }
+    /// Marks everything reachable from the stack, the globals and the
+    /// running chunk's constants, then frees the rest of the heap, or of
+    /// the nursery if `young` is set. The compiler never allocates while
+    /// the vm runs so it holds no roots.
+    pub(crate) fn collect_garbage<'a, 'b>(state: &mut RuntimeState<'a, 'b>, young: bool) {
+        let chunk = state.frames.chunk;
+        let vm = &*state.vm;
+        let mark_roots = |heap: &mut Heap| {
+            vm.stack.iter().for_each(|v| heap.mark_value(*v));
+            for global in vm.globals.iter() {
+                heap.mark_object(Object::from_ptr(&global.name));
+                global.value.into_iter().for_each(|v| heap.mark_value(v));
+            }
+            chunk.constants().iter().for_each(|v| heap.mark_value(*v));
+        };
+        if young {
+            vm.allocator.collect_young(mark_roots);
+        } else {
+            vm.allocator.collect_garbage(mark_roots);
+        }
     }
 }
@@ -1,73 +1,75 @@
-use std::ops::{Add, AddAssign, Sub, SubAssign};
+/// The number of values the stack holds before it grows.
+const STACK_INITIAL: usize = 256;
+/// The default number of values the stack may hold.
+pub(crate) const STACK_MAX: usize = 64 * 1024;
 
-pub(crate) const STACK_MAX: usize = 256;
-#[repr(transparent)]
-#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct StackTop(usize);
+/// Returned by [`Stack::push`] when the stack is already at its limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StackOverflow;
 
-impl From<usize> for StackTop {
-    fn from(value: usize) -> Self {
-        Self(value)
-    }
-}
-impl Add<usize> for StackTop {
-    type Output = usize;
-    fn add(self, rhs: usize) -> Self::Output {
-        self.0 + rhs
-    }
-}
-impl Sub<usize> for StackTop {
-    type Output = usize;
-    fn sub(self, rhs: usize) -> Self::Output {
-        self.0 - rhs
-    }
-}
-impl AddAssign for StackTop {
-    fn add_assign(&mut self, rhs: Self) {
-        self.0 += rhs.0;
-    }
-}
-impl SubAssign for StackTop {
-    fn sub_assign(&mut self, rhs: Self) {
-        self.0 -= rhs.0;
-    }
-}
 #[derive(Debug)]
 pub(crate) struct Stack<T: Copy> {
-    stack_top: StackTop,
-    data: [Option<T>; STACK_MAX],
+    data: Vec<T>,
+    limit: usize,
 }
 
 impl<T: Copy> Stack<T> {
-    pub(crate) fn new() -> Self {
+    /// Creates a stack that grows on demand up to `limit` values.
+    pub(crate) fn new(limit: usize) -> Self {
         Self {
-            stack_top: StackTop::default(),
-            data: [None; STACK_MAX],
+            data: Vec::with_capacity(STACK_INITIAL.min(limit)),
+            limit,
         }
     }
-    /// # Panics
-    /// will panic if a len of [`STACK_TOP`] is exceded.
-    pub(crate) fn push(&mut self, value: T) {
-        if self.stack_top.0 == STACK_MAX {
-            panic!("stack overflow");
+    pub(crate) fn push(&mut self, value: T) -> Result<(), StackOverflow> {
+        if self.data.len() == self.limit {
+            return Err(StackOverflow);
         }
-        self.stack_top += 1.into();
-        self.data[self.stack_top - 1] = Some(value);
+        self.data.push(value);
+        Ok(())
     }
     pub(crate) fn pop(&mut self) -> Option<T> {
-        if self.stack_top.0 > 0 {
-            self.stack_top -= 1.into();
-        }
-        self.data[self.stack_top.0].take()
+        self.data.pop()
     }
     pub(crate) fn peek(&self, span: usize) -> Option<&T> {
-        if self.stack_top >= span.into() && self.stack_top > 0.into() {
-            self.data[self.stack_top - 1 - span].as_ref()
-        } else {
-            None
+        self.data.iter().nth_back(span)
+    }
+    /// Returns the value `index` slots above the bottom of the stack.
+    pub(crate) fn get(&self, index: usize) -> Option<&T> {
+        self.data.get(index)
+    }
+    /// Overwrites the value `index` slots above the bottom of the stack.
+    pub(crate) fn set(&mut self, index: usize, value: T) {
+        if let Some(slot) = self.data.get_mut(index) {
+            *slot = value;
         }
     }
+    pub(crate) fn len(&self) -> usize {
+        self.data.len()
+    }
+    /// Iterates over the values on the stack from the bottom up.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &T> {
+        self.data.iter()
+    }
     pub(crate) fn reset(&mut self) {
-        self.stack_top = 0.into();
+        self.data.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn grows_up_to_limit() {
+        let mut stack = Stack::new(STACK_INITIAL * 2);
+        for n in 0..STACK_INITIAL * 2 {
+            assert_eq!(Ok(()), stack.push(n));
+        }
+        assert_eq!(Err(StackOverflow), stack.push(0));
+        assert_eq!(Some(&(STACK_INITIAL * 2 - 1)), stack.peek(0));
+        assert_eq!(Some(&1), stack.get(1));
+        assert_eq!(Some(STACK_INITIAL * 2 - 1), stack.pop());
+        assert_eq!(Ok(()), stack.push(0));
     }
 }
@@ -0,0 +1,47 @@
+//! This module provides the setup shared by tests that compile or run
+//! scripts.
+use crate::{
+    byte_code::{Chunk, ChunkBuilder, Location, OpCode},
+    compiler::{CompilerResult, Parser},
+    frame::CallFrame,
+    heap::{GcMode, Heap},
+    run_time::{self, vm::Vm, RuntimeState},
+};
+
+/// A vm and the heap it allocates in. The heap is boxed since the vm's
+/// allocator points at it.
+pub(crate) struct TestVm {
+    pub(crate) vm: Vm,
+    heap: Box<Heap>,
+}
+
+impl TestVm {
+    pub(crate) fn new() -> Self {
+        Self::with_heap(Heap::new())
+    }
+    pub(crate) fn with_heap(heap: Heap) -> Self {
+        let mut heap = Box::new(heap);
+        let vm = Vm::new(heap.allocator(), GcMode::MarkSweep, 1024);
+        Self { vm, heap }
+    }
+    /// Compiles `source`, returning its code or the first error.
+    pub(crate) fn compile(
+        &mut self,
+        source: &str,
+        optimize: bool,
+    ) -> CompilerResult<Vec<(OpCode, Location)>> {
+        Parser::new(source, self.heap.allocator(), &mut self.vm.globals)
+            .with_optimizations(optimize)
+            .collect()
+    }
+    /// Compiles `source` without optimizations and runs it on the stack
+    /// machine, returning the error it fails with.
+    pub(crate) fn run(&mut self, source: &str) -> Result<(), String> {
+        let code = self.compile(source, false).map_err(|err| err.to_string())?;
+        let chunk = Chunk::try_from(code.into_iter().collect::<ChunkBuilder>())
+            .map_err(|err| err.to_string())?;
+        let mut frame = CallFrame::new(&chunk);
+        run_time::run(&mut RuntimeState::new(&mut self.vm, &mut frame))
+            .map_err(|err| err.to_string())
+    }
+}
@@ -2,6 +2,7 @@ use std::{fmt::Display, ops::Not};
 
 use crate::heap::Object;
 
+#[cfg(not(feature = "compact-value"))]
 #[derive(Default, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub(crate) enum Value {
     #[default]
@@ -10,31 +11,25 @@ pub(crate) enum Value {
     Bool(bool),
     Object(Object),
 }
+#[cfg(not(feature = "compact-value"))]
 impl From<Object> for Value {
     fn from(value: Object) -> Self {
         Self::Object(value)
     }
 }
+#[cfg(not(feature = "compact-value"))]
 impl From<i64> for Value {
     fn from(value: i64) -> Self {
         Self::Number(value)
     }
 }
+#[cfg(not(feature = "compact-value"))]
 impl From<bool> for Value {
     fn from(value: bool) -> Self {
         Self::Bool(value)
     }
 }
-impl Not for Value {
-    type Output = Value;
-    fn not(self) -> Self::Output {
-        Value::Bool(match self {
-            Self::Nil => true,
-            Self::Bool(b) => !b,
-            _ => false,
-        })
-    }
-}
+#[cfg(not(feature = "compact-value"))]
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -46,6 +41,7 @@ impl Display for Value {
     }
 }
 
+#[cfg(not(feature = "compact-value"))]
 impl Value {
     /// Returns `true` if the value is [`Bool`].
     ///
@@ -87,6 +83,14 @@ impl Value {
         }
     }
 
+    pub(crate) fn try_into_object(self) -> Result<Object, Self> {
+        if let Self::Object(v) = self {
+            Ok(v)
+        } else {
+            Err(self)
+        }
+    }
+
     /// Returns `true` if the value is [`Object`].
     ///
     /// [`Object`]: Value::Object
@@ -95,3 +99,186 @@ impl Value {
         matches!(self, Self::Object(..))
     }
 }
+
+/// A value packed into one word by tagging its low bits:
+///
+/// - `...1`: a number that fits in 63 bits, shifted left.
+/// - `..10`: a bigger number, boxed by [`box_number`], at the box's address.
+/// - `..00`: an object, at the address of its
+///   [`HeapObject`](crate::heap::HeapObject), or `nil`, `false` and `true`,
+///   which are the numbers below any address.
+#[cfg(feature = "compact-value")]
+#[derive(Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub(crate) struct Value(u64);
+#[cfg(feature = "compact-value")]
+const _: () = assert!(
+    std::mem::align_of::<crate::heap::HeapObject>() >= 4 && std::mem::align_of::<i64>() >= 4
+);
+#[cfg(feature = "compact-value")]
+const NIL: u64 = 0;
+#[cfg(feature = "compact-value")]
+const FALSE: u64 = 4;
+#[cfg(feature = "compact-value")]
+const TRUE: u64 = 8;
+#[cfg(feature = "compact-value")]
+const BOXED_TAG: u64 = 2;
+
+/// Returns the tagged address of a box holding `n`. Each number gets one
+/// box, so values are equal exactly when their bits are. The boxes are never
+/// freed, which costs a few bytes for each distinct number too big to tag.
+#[cfg(feature = "compact-value")]
+fn box_number(n: i64) -> u64 {
+    use std::{collections::BTreeMap, sync::Mutex};
+    static BOXES: Mutex<BTreeMap<i64, &'static i64>> = Mutex::new(BTreeMap::new());
+    let mut boxes = BOXES.lock().unwrap();
+    let boxed: *const i64 = *boxes.entry(n).or_insert_with(|| Box::leak(Box::new(n)));
+    boxed.expose_provenance() as u64 | BOXED_TAG
+}
+#[cfg(feature = "compact-value")]
+impl From<Object> for Value {
+    fn from(value: Object) -> Self {
+        Self(value.addr())
+    }
+}
+#[cfg(feature = "compact-value")]
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        let bits = value << 1;
+        if bits >> 1 == value {
+            Self(bits as u64 | 1)
+        } else {
+            Self(box_number(value))
+        }
+    }
+}
+#[cfg(feature = "compact-value")]
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Self(if value { TRUE } else { FALSE })
+    }
+}
+#[cfg(feature = "compact-value")]
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Ok(n) = self.try_into_number() {
+            f.debug_tuple("Number").field(&n).finish()
+        } else if let Ok(b) = self.try_into_bool() {
+            f.debug_tuple("Bool").field(&b).finish()
+        } else if let Ok(o) = self.try_into_object() {
+            f.debug_tuple("Object").field(&o).finish()
+        } else {
+            write!(f, "Nil")
+        }
+    }
+}
+#[cfg(feature = "compact-value")]
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Ok(n) = self.try_into_number() {
+            write!(f, "{n}")
+        } else if let Ok(b) = self.try_into_bool() {
+            write!(f, "{b}")
+        } else if let Ok(o) = self.try_into_object() {
+            write!(f, "{o}")
+        } else {
+            write!(f, "nil")
+        }
+    }
+}
+
+#[cfg(feature = "compact-value")]
+impl Value {
+    /// Returns `true` if the value is a bool.
+    #[must_use]
+    pub(crate) fn is_bool(&self) -> bool {
+        self.0 == FALSE || self.0 == TRUE
+    }
+
+    /// Returns `true` if the value is a number.
+    #[must_use]
+    pub(crate) fn is_number(&self) -> bool {
+        self.0 & 1 == 1 || self.0 & 3 == BOXED_TAG
+    }
+
+    /// Returns `true` if the value is `nil`.
+    #[must_use]
+    pub(crate) fn is_nil(&self) -> bool {
+        self.0 == NIL
+    }
+
+    #[inline(always)]
+    pub(crate) fn try_into_number(self) -> Result<i64, Self> {
+        if self.0 & 1 == 1 {
+            Ok(self.0 as i64 >> 1)
+        } else if self.0 & 3 == BOXED_TAG {
+            let boxed: *const i64 =
+                std::ptr::with_exposed_provenance((self.0 ^ BOXED_TAG) as usize);
+            // Boxes are leaked, so they live as long as any value.
+            Ok(unsafe { *boxed })
+        } else {
+            Err(self)
+        }
+    }
+
+    pub(crate) fn try_into_bool(self) -> Result<bool, Self> {
+        match self.0 {
+            FALSE => Ok(false),
+            TRUE => Ok(true),
+            _ => Err(self),
+        }
+    }
+
+    pub(crate) fn try_into_object(self) -> Result<Object, Self> {
+        if self.is_object() {
+            Ok(Object::from_addr(self.0))
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Returns `true` if the value is an object.
+    #[must_use]
+    pub(crate) fn is_object(&self) -> bool {
+        self.0 & 3 == 0 && self.0 > TRUE
+    }
+}
+
+impl Not for Value {
+    type Output = Value;
+    fn not(self) -> Self::Output {
+        self.is_falsey().into()
+    }
+}
+
+impl Value {
+    /// Returns `true` if the value is `nil` or `false`.
+    #[must_use]
+    pub(crate) fn is_falsey(&self) -> bool {
+        self.is_nil() || self.try_into_bool() == Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::mem::size_of;
+
+    #[test]
+    fn value_size() {
+        if cfg!(feature = "compact-value") {
+            assert_eq!(8, size_of::<Value>());
+        } else {
+            assert_eq!(3 * size_of::<usize>(), size_of::<Value>());
+        }
+    }
+    #[test]
+    fn numbers_keep_64_bits() {
+        for n in [i64::MIN, -1, 0, 1 << 62, i64::MAX] {
+            let value = Value::from(n);
+            assert_eq!(Ok(n), value.try_into_number());
+            assert_eq!(value, Value::from(n));
+            assert_eq!(n.to_string(), value.to_string());
+        }
+        assert_ne!(Value::from(i64::MAX), Value::from(i64::MIN));
+    }
+}
@@ -0,0 +1,156 @@
+//! Runs every script in `tests/golden` on the stack machine, on the register
+//! machine and, translated by `rlox build`, as a C program. Each must print
+//! exactly what the script's `.out` and `.err` files hold, a missing file
+//! standing for no output, and fail exactly when something is expected on
+//! stderr. A script starting with `// args:` is run and built with the
+//! options that follow.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+const RLOX: &str = env!("CARGO_BIN_EXE_rlox");
+
+fn scripts() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut scripts: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+    scripts
+}
+
+fn args(script: &Path) -> Vec<String> {
+    let source = fs::read_to_string(script).unwrap();
+    let first = source.lines().next().unwrap_or_default();
+    let args = first.strip_prefix("// args:").unwrap_or_default();
+    args.split_whitespace().map(String::from).collect()
+}
+
+fn check(script: &Path, path: &str, stdout: &[u8], stderr: &[u8], success: bool) {
+    let expected = |ext| fs::read_to_string(script.with_extension(ext)).unwrap_or_default();
+    let (out, err) = (expected("out"), expected("err"));
+    let script = script.display();
+    assert_eq!(
+        out,
+        String::from_utf8_lossy(stdout),
+        "stdout of {script} {path}"
+    );
+    assert_eq!(
+        err,
+        String::from_utf8_lossy(stderr),
+        "stderr of {script} {path}"
+    );
+    assert_eq!(err.is_empty(), success, "exit status of {script} {path}");
+}
+
+fn run(command: &mut Command) -> Output {
+    command.output().unwrap()
+}
+
+#[test]
+fn vm_backends() {
+    for script in scripts() {
+        for backend in ["stack", "register"] {
+            let output = run(Command::new(RLOX)
+                .arg(format!("--backend={backend}"))
+                .args(args(&script))
+                .arg(&script));
+            let path = format!("on the {backend} machine");
+            let success = output.status.success();
+            check(&script, &path, &output.stdout, &output.stderr, success);
+        }
+    }
+}
+
+#[test]
+fn c_programs() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    fs::create_dir_all(&dir).unwrap();
+    for script in scripts() {
+        let program = dir.join(script.file_stem().unwrap());
+        let source = program.with_extension("c");
+        let build = run(Command::new(RLOX)
+            .arg("build")
+            .args(args(&script))
+            .arg(&script)
+            .arg("-o")
+            .arg(&source));
+        if !build.status.success() {
+            // Compiler errors are reported by the build.
+            check(&script, "built", &build.stdout, &build.stderr, false);
+            continue;
+        }
+        let cc = Command::new("cc")
+            .args(["-std=c11", "-Wall", "-Wextra", "-Werror", "-o"])
+            .arg(&program)
+            .arg(&source)
+            .status();
+        let Ok(cc) = cc else {
+            eprintln!("No C compiler, skipping the C programs.");
+            return;
+        };
+        assert!(cc.success(), "cc failed on {}", source.display());
+        let output = run(&mut Command::new(&program));
+        // Compiler warnings are reported by the build, the rest by the program.
+        let stderr = [build.stderr, output.stderr].concat();
+        let success = output.status.success();
+        check(&script, "compiled to C", &output.stdout, &stderr, success);
+    }
+}
+
+#[test]
+fn build_rejects_vm_options() {
+    let script = &scripts()[0];
+    let source = Path::new(env!("CARGO_TARGET_TMPDIR")).join("rejected.c");
+    for option in [
+        "--gc=generational",
+        "--gc-stats",
+        "--max-heap=1024",
+        "--backend=register",
+        "--disassemble",
+    ] {
+        let build = run(Command::new(RLOX)
+            .args(["build", option])
+            .arg(script)
+            .arg("-o")
+            .arg(&source));
+        let stderr = String::from_utf8_lossy(&build.stderr);
+        assert!(!build.status.success(), "{option} was accepted");
+        assert!(stderr.contains("doesn't apply to a build"), "{stderr}");
+    }
+}
+
+#[test]
+fn build_concatenates_into_ropes() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("pieces");
+    fs::create_dir_all(&dir).unwrap();
+    let (script, program) = (dir.join("pieces.lox"), dir.join("pieces"));
+    let pieces = "s = s + \"0123456789\";\n".repeat(2000);
+    let source = format!("var s = \"\";\n{pieces}print s == s + \"\";\nprint s;\n");
+    fs::write(&script, source).unwrap();
+    let build = run(Command::new(RLOX)
+        .arg("build")
+        .arg(&script)
+        .arg("-o")
+        .arg(program.with_extension("c")));
+    assert!(build.status.success());
+    let cc = Command::new("cc")
+        .args(["-std=c11", "-o"])
+        .arg(&program)
+        .arg(program.with_extension("c"))
+        .status();
+    let Ok(cc) = cc else {
+        eprintln!("No C compiler, skipping the C program.");
+        return;
+    };
+    assert!(cc.success());
+    let expected = run(Command::new(RLOX).arg(&script));
+    let output = run(&mut Command::new(&program));
+    assert!(output.status.success());
+    assert_eq!(expected.stdout, output.stdout);
+}